@@ -0,0 +1,505 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use ctxmgr::{self, ContextManager};
+use hours::{self, Clock};
+use prompt;
+use script::{self, ScriptBase, Action};
+use ssml;
+use twiml::{self, Twiml};
+use url;
+use webhook;
+
+
+/// What to send back for one request from twilio
+pub struct Reply {
+    pub twiml: Twiml,
+    /// Everything the caller will hear, already templated. Audio files are still written as [play:...]
+    pub said: String,
+    /// The path the caller's next input will be sent to, None once the call is over
+    pub next_path: Option<String>,
+    /// Whether twilio comes back to next_path when the caller doesn't say anything, otherwise the call ends
+    pub handles_no_input: bool,
+    /// Texts the script reached on the way, respond doesn't send them itself
+    pub texts: Vec<OutgoingText>,
+}
+
+#[derive(Debug)]
+pub struct OutgoingText {
+    pub to: String,
+    pub body: String,
+}
+
+pub enum Outcome {
+    Reply(Reply),
+    /// The script reached a Webhook, once it's answered webhook_done carries on from there
+    Webhook(WebhookCall),
+}
+
+/// A webhook request to send, along with what respond had already worked out before reaching it
+pub struct WebhookCall {
+    /// The Webhook action's path
+    pub path: String,
+    pub url: String,
+    /// The JSON to POST
+    pub body: String,
+    /// Seconds to wait for an answer before following the fallback
+    pub timeout: u32,
+    err_prefix: Option<(String, String)>,
+    texts: Vec<OutgoingText>,
+    caller: Option<String>,
+    gave_up: bool,
+}
+
+
+pub fn template<T>(raw: &str, vars: &ctxmgr::CallVars<T>) -> String where T: ctxmgr::Context {
+    template_with(raw, vars, str::to_owned)
+}
+
+/// template for text that's said, the values can't add SSML tags
+fn template_said<T>(raw: &str, vars: &ctxmgr::CallVars<T>) -> String where T: ctxmgr::Context {
+    template_with(raw, vars, ssml::escape)
+}
+
+/// template for a url, the values are percent-encoded so they can't add to its path or query
+fn template_url<T>(raw: &str, vars: &ctxmgr::CallVars<T>) -> String where T: ctxmgr::Context {
+    template_with(raw, vars, |value| url::form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>().replace('+', "%20"))
+}
+
+fn template_with<T>(raw: &str, vars: &ctxmgr::CallVars<T>, encode: fn(&str) -> String) -> String where T: ctxmgr::Context {
+    let mut owned_copy = String::from(raw);
+    for var_name in vars.list_vars().iter() {
+        let to_replace = format!("{{{}}}", var_name);
+        let substitution:&str = vars.resolve_variable(var_name).expect("CTX was unable to resolve variable");
+        owned_copy = owned_copy.replace(&to_replace, &encode(substitution));
+    }
+    owned_copy
+}
+
+
+/// Works out what to do with one request for call id, given the path it was sent to and twilio's
+/// body params (Digits, SpeechResult...). no_input is set when the prompt at path timed out without
+/// the caller saying anything. Shared by the server and the offline simulator so both walk the
+/// script the same way. callback_url is where the next request should be sent and script_name is
+/// the name sb is served under, clock is what Hours actions check the time with.
+pub fn respond<T>(sb: &ScriptBase, ctx_mgr: &RefCell<ContextManager<T>>, id: i32, path: &str, no_input: bool,
+                  body_params: &HashMap<String, String>, callback_url: &str, script_name: &str, clock: &Clock)
+                  -> Result<Outcome, &'static str>
+    where T: ctxmgr::Context + ::std::fmt::Debug {
+    if ctx_mgr.borrow().load_context(id).is_none() {
+        return Err("Unknown call id");
+    }
+    let str_opt_digits = body_params.get("Digits");
+    let str_opt_speech = body_params.get("SpeechResult");
+
+    let current = sb.follow_path(path);
+    if current.is_none() {
+        let default_voice = voice(&sb.languages, &sb.languages.default);
+        return Ok(Outcome::Reply(hangup(Speech::new(callback_url).add("Invalid path", &default_voice))));
+    }
+    let (cur_action, cur_path) = current.unwrap();
+
+    // Set when the prompt that was waiting for the input has no use for it
+    let mut invalid_for: Option<&Action> = None;
+    // Said before whatever comes next when the caller's input was invalid or there wasn't any
+    let mut err_message = None;
+    // Set when what comes next is a prompt's error, retry limit or no input action, a hangup reached
+    // from there means the call was given up on rather than finished
+    let mut gave_up = false;
+
+    let desired_action = match (cur_action, str_opt_digits) {
+        _ if no_input => {
+            let attempts = ctx_mgr.borrow_mut().record_no_input(id, &cur_path);
+            println!("No input for {:?}, attempt {}", cur_path, attempts);
+            gave_up = true;
+            if let Some(no_input) = cur_action.no_input() {
+                err_message = no_input.message.as_ref().map(|msg| (msg.as_ref(), &no_input.translations));
+                if no_input.exceeded(attempts) {
+                    ctx_mgr.borrow_mut().clear_no_inputs(id, &cur_path);
+                }
+            }
+            sb.follow_no_input(&cur_path, attempts)
+        }
+        // Sent by the <Record>'s action once the caller is done, Digits is set too if they pressed the finish key
+        (&Action::Record(ref record), _) if body_params.contains_key("RecordingUrl") => {
+            let duration = body_params.get("RecordingDuration").and_then(|d| d.parse::<u32>().ok());
+            match ctx_mgr.borrow_mut().recording_mut(id, &record.name, &body_params["RecordingUrl"]) {
+                Some(recording) => recording.duration = duration,
+                None => println!("Call {} can't take {:?} as its {:?} recording", id, body_params["RecordingUrl"], record.name),
+            }
+            sb.follow_path(&format!("{}{}", cur_path, script::NEXT_STEP))
+        }
+        // Sent by the <Dial>'s action once the transfer is over
+        (&Action::Transfer(_), None) if body_params.contains_key("DialCallStatus") => {
+            let status = &body_params["DialCallStatus"];
+            println!("Transfer at {:?} finished with {:?}", cur_path, status);
+            if status == "completed" || status == "answered" {
+                // The caller has talked to someone, there's nothing left for the script to do
+                ctx_mgr.borrow_mut().session_mut(id).ok_or("Unknown call id")?.completed = true;
+                return Ok(Outcome::Reply(Reply { twiml: twiml::hangup(), said: String::new(), next_path: None, handles_no_input: false, texts: Vec::new() }));
+            }
+            sb.follow_path(&format!("{}{}", cur_path, script::FALLBACK_STEP))
+        }
+        // A Collect stores everything that was typed instead of following it as a path
+        (&Action::Collect(ref collect), Some(dig)) => {
+            // Twilio sends whatever was typed when the caller stops early, so a fixed length has to be checked too
+            let wrong_length = collect.num_digits.map_or(false, |n| dig.chars().count() != n as usize);
+            if dig.is_empty() || !dig.chars().all(|c| c.is_digit(10)) || wrong_length {
+                invalid_for = Some(cur_action);
+                None
+            }
+            else {
+                ctx_mgr.borrow_mut().store_input(id, &collect.save_as, dig.clone());
+                sb.follow_path(&format!("{}{}", cur_path, script::NEXT_STEP))
+            }
+        }
+        (&Action::ExecuteScript(ref script), None) if str_opt_speech.is_some() => {
+            let confidence = body_params.get("Confidence").and_then(|c| c.parse::<f32>().ok()).unwrap_or(0.0);
+            let speech = str_opt_speech.unwrap();
+            println!("Heard {:?} with confidence {}", speech, confidence);
+
+            let language = {
+                let ctx_mgr_ref = ctx_mgr.borrow();
+                call_language(&sb.languages, &ctx_mgr_ref.load_call_vars(id).ok_or("Unknown call id")?)
+            };
+            match script.match_speech(speech, confidence, script.listens_in(&language, &sb.languages.default)) {
+                Some(key) => sb.follow_path(&format!("{}{}", cur_path, script::key_char(key))),
+                None => {
+                    invalid_for = Some(cur_action);
+                    None
+                }
+            }
+        }
+        (_, Some(dig)) => {
+            let mut keys = dig.chars();
+            let key = keys.next().and_then(script::key_index).ok_or("Couldn't parse digits")?;
+            if keys.next().is_some() {
+                return Err("Digits should be a single character only!");
+            }
+            let new_path = format!("{}{}", cur_path, script::key_char(key));
+            match cur_action {
+                &Action::ExecuteScript(_) if sb.node_at(&new_path).is_none() => {
+                    invalid_for = Some(cur_action);
+                    None
+                }
+                _ => sb.follow_path(&new_path),
+            }
+        }
+        (_, None) => Some((cur_action, cur_path.clone())),
+    };
+
+    let desired_action = match invalid_for {
+        Some(prompt) => {
+            let attempts = ctx_mgr.borrow_mut().record_invalid_input(id, &cur_path);
+            println!("Invalid input for {:?}, attempt {}", cur_path, attempts);
+            gave_up = true;
+            err_message = prompt.err_message();
+            if prompt.retries_exceeded(attempts) {
+                // Start counting again in case the retry limit action comes back to this script
+                ctx_mgr.borrow_mut().clear_invalid_inputs(id, &cur_path);
+            }
+            sb.follow_invalid_input(&cur_path, attempts)
+        }
+        None if no_input => desired_action,
+        None => {
+            let mut ctx_mgr_mut = ctx_mgr.borrow_mut();
+            ctx_mgr_mut.clear_invalid_inputs(id, &cur_path);
+            if str_opt_digits.is_some() || str_opt_speech.is_some() {
+                ctx_mgr_mut.clear_no_inputs(id, &cur_path);
+            }
+            if let &Action::ExecuteScript(script::Script { save_as: Some(ref name), .. }) = cur_action {
+                if let Some(input) = str_opt_speech.or(str_opt_digits) {
+                    ctx_mgr_mut.store_input(id, name, input.clone());
+                }
+            }
+            desired_action
+        }
+    };
+
+    // The error message and the locale it's said in
+    let err_prefix = match err_message {
+        Some((msg, translations)) => {
+            let ctx_mgr_ref = ctx_mgr.borrow();
+            let this_ctx = ctx_mgr_ref.load_call_vars(id).ok_or("Unknown call id")?;
+            let language = call_language(&sb.languages, &this_ctx);
+            let (text, locale) = sb.languages.pick(&language, msg, translations);
+            Some((format!("{} ", template_said(text, &this_ctx)), locale.to_owned()))
+        }
+        None => None,
+    };
+    continue_from(sb, ctx_mgr, id, desired_action, err_prefix, Vec::new(), caller_number(body_params), gave_up, callback_url, script_name, clock)
+}
+
+/// Carries on from a Webhook once it has answered with body, or failed with why it couldn't
+pub fn webhook_done<T>(sb: &ScriptBase, ctx_mgr: &RefCell<ContextManager<T>>, id: i32, call: WebhookCall,
+                       answer: Result<String, String>, callback_url: &str, script_name: &str, clock: &Clock)
+                       -> Result<Outcome, &'static str>
+    where T: ctxmgr::Context + ::std::fmt::Debug {
+    let next_step = format!("{}{}", call.path, script::NEXT_STEP);
+    let fallback = format!("{}{}", call.path, script::FALLBACK_STEP);
+
+    let next_path = match answer.and_then(|body| webhook::parse_decision(&body)) {
+        Ok(decision) => {
+            println!("Webhook at {:?} answered {:?}", call.path, decision);
+            for (name, value) in decision.string_vars() {
+                ctx_mgr.borrow_mut().store_input(id, &name, value);
+            }
+            match decision.goto_label {
+                Some(ref label) => match sb.path_of_label(label) {
+                    Some(label_path) => label_path.to_owned(),
+                    None => {
+                        println!("Webhook at {:?} picked unknown label {:?}", call.path, label);
+                        fallback
+                    }
+                },
+                None => next_step,
+            }
+        }
+        Err(e) => {
+            println!("Webhook at {:?} failed: {}", call.path, e);
+            fallback
+        }
+    };
+    continue_from(sb, ctx_mgr, id, sb.follow_path(&next_path), call.err_prefix, call.texts, call.caller, call.gave_up, callback_url, script_name, clock)
+}
+
+/// Goes through the actions the caller doesn't hear, starting at desired_action, and replies with
+/// the first one they do. Stops at a Webhook instead since its answer has to be waited for
+fn continue_from<'a, T>(sb: &'a ScriptBase, ctx_mgr: &RefCell<ContextManager<T>>, id: i32, mut desired_action: Option<(&'a Action, String)>,
+                    err_prefix: Option<(String, String)>, mut texts: Vec<OutgoingText>, caller: Option<String>, gave_up: bool, callback_url: &str, script_name: &str,
+                    clock: &Clock)
+                    -> Result<Outcome, &'static str>
+    where T: ctxmgr::Context + ::std::fmt::Debug {
+    println!("The desired action is {:?}", desired_action);
+    println!("{:?}", *ctx_mgr.borrow());
+
+    // Branches, Hours, Splits, sub-flows, texts and language changes don't say anything to the caller so keep going until we reach something that does
+    loop {
+        // Changing the language and recording a Split's variant need the session mutably, so they're done before the call's variables are borrowed
+        if let Some((&Action::SetLanguage(ref set), ref path)) = desired_action {
+            println!("Call {} is now in {}", id, set.language);
+            ctx_mgr.borrow_mut().session_mut(id).ok_or("Unknown call id")?.language = Some(set.language.clone());
+            desired_action = sb.follow_path(&format!("{}{}", path, script::NEXT_STEP));
+            continue;
+        }
+        if let Some((&Action::Split(ref split), ref path)) = desired_action {
+            desired_action = match split.pick(id) {
+                Some(i) => {
+                    println!("Call {} gets variant {:?} of {:?}", id, split.variants[i].name, split.name);
+                    ctx_mgr.borrow_mut().session_mut(id).ok_or("Unknown call id")?.variants.insert(split.name.clone(), split.variants[i].name.clone());
+                    sb.follow_path(&format!("{}{}", path, script::key_char(i)))
+                }
+                None => None,
+            };
+            continue;
+        }
+
+        let ctx_mgr_ref = ctx_mgr.borrow();
+        let this_ctx = ctx_mgr_ref.load_call_vars(id).ok_or("Unknown call id")?;
+        let next_path = match desired_action {
+            Some((&Action::Branch(ref branch), ref path)) => {
+                let holds = branch.condition.holds(|var| this_ctx.resolve_variable(var).map(str::to_owned));
+                format!("{}{}", path, if holds { script::THEN_STEP } else { script::ELSE_STEP })
+            }
+            Some((&Action::Hours(ref hours), ref path)) => {
+                let status = hours.schedule.status(clock.now());
+                println!("Hours at {:?} are {:?}", path, status);
+                let step = match status {
+                    hours::Status::Open => script::OPEN_STEP,
+                    hours::Status::Holiday if hours.holiday.is_some() => script::HOLIDAY_STEP,
+                    _ => script::CLOSED_STEP,
+                };
+                format!("{}{}", path, step)
+            }
+            Some((&Action::SubFlow(ref sub), ref path)) => {
+                println!("Call {} is running sub-flow {:?}", id, sub.name);
+                format!("{}{}", path, script::SUBFLOW_STEP)
+            }
+            Some((&Action::Return, ref path)) => match sb.return_path(path) {
+                Some(return_path) => return_path,
+                None => break,
+            },
+            Some((&Action::SendText(ref text), ref path)) => {
+                match text.to.as_ref().map(|to| template(to, &this_ctx)).or_else(|| caller.clone()) {
+                    Some(to) => {
+                        let language = call_language(&sb.languages, &this_ctx);
+                        let (body, _) = sb.languages.pick(&language, &text.text, &text.translations);
+                        texts.push(OutgoingText { to, body: template(body, &this_ctx) })
+                    }
+                    None => println!("Not sending text at {:?}, there's no number for call {}", path, id),
+                }
+                format!("{}{}", path, script::NEXT_STEP)
+            }
+            Some((&Action::Webhook(ref hook), ref path)) => {
+                let vars = this_ctx.list_vars().into_iter()
+                    .filter_map(|var| this_ctx.resolve_variable(var).map(|value| (var, value)))
+                    .collect::<Vec<(&str, &str)>>();
+                return Ok(Outcome::Webhook(WebhookCall {
+                    path: path.clone(),
+                    url: template_url(&hook.url, &this_ctx),
+                    body: webhook::request_body(id, path, &vars),
+                    timeout: hook.timeout,
+                    err_prefix,
+                    texts,
+                    caller,
+                    gave_up,
+                }));
+            }
+            _ => break,
+        };
+        desired_action = sb.follow_path(&next_path);
+    }
+
+    if let Some((&Action::Record(ref record), _)) = desired_action {
+        ctx_mgr.borrow_mut().expect_recording(id, &record.name);
+    }
+    if let Some((&Action::HangupWithMessage(_), _)) = desired_action {
+        if !gave_up {
+            ctx_mgr.borrow_mut().session_mut(id).ok_or("Unknown call id")?.completed = true;
+        }
+    }
+
+    let ctx_mgr_ref = ctx_mgr.borrow();
+    let this_ctx = ctx_mgr_ref.load_call_vars(id).ok_or("Unknown call id")?;
+
+    // Anything without a translation for the call's language is said in the default language
+    let languages = &sb.languages;
+    let language = call_language(languages, &this_ctx);
+    let default_voice = voice(languages, &languages.default);
+    let speech = match err_prefix {
+        Some((ref text, ref locale)) => Speech::new(callback_url).add(text, &voice(languages, locale)),
+        None => Speech::new(callback_url),
+    };
+
+    // These should really all require a hmac
+    let mut reply = match desired_action {
+        Some((&Action::ExecuteScript(ref script), ref new_path)) => {
+            let listens_in = script.listens_in(&language, &languages.default);
+            let gather = twiml::Gather {
+                input: script.input.twiml_name(),
+                hints: script.all_keywords(listens_in).iter().map(|w| w.to_string()).collect(),
+                language: Some(listens_in.to_owned()),
+                timeout: script.timeout,
+                ..twiml::Gather::single_key()
+            };
+            let (text, locale) = languages.pick(&language, &script.text, &script.translations);
+            gather_input(&path_url(callback_url, script_name, id, new_path), new_path, speech.add(&template_said(text, &this_ctx), &voice(languages, locale)), gather, script.no_input.is_some())
+        }
+        Some((&Action::Collect(ref collect), ref new_path)) => {
+            let gather = twiml::Gather {
+                num_digits: collect.num_digits,
+                finish_on_key: Some(collect.finish_on_key),
+                timeout: collect.timeout,
+                ..twiml::Gather::single_key()
+            };
+            let (text, locale) = languages.pick(&language, &collect.text, &collect.translations);
+            gather_input(&path_url(callback_url, script_name, id, new_path), new_path, speech.add(&template_said(text, &this_ctx), &voice(languages, locale)), gather, collect.no_input.is_some())
+        }
+        Some((&Action::HangupWithMessage(ref hangup_msg), _)) => {
+            let (text, locale) = languages.pick(&language, &hangup_msg.text, &hangup_msg.translations);
+            hangup(speech.add(&template_said(text, &this_ctx), &voice(languages, locale)))
+        }
+        Some((&Action::Transfer(ref transfer), ref new_path)) => {
+            let speech = match transfer.text {
+                Some(ref text) => {
+                    let (text, locale) = languages.pick(&language, text, &transfer.translations);
+                    speech.add(&template_said(text, &this_ctx), &voice(languages, locale))
+                }
+                None => speech,
+            };
+            let dial = twiml::Dial {
+                to: template(&transfer.to, &this_ctx),
+                caller_id: transfer.caller_id.as_ref().map(|c| template(c, &this_ctx)),
+                timeout: transfer.timeout,
+            };
+            println!("Transferring call {} to {}", id, dial.to);
+            let twiml = twiml::dial(&path_url(callback_url, script_name, id, new_path), &speech.segments, &dial);
+            Reply { twiml, said: speech.said, next_path: Some(new_path.clone()), handles_no_input: false, texts: Vec::new() }
+        }
+        Some((&Action::Record(ref record), ref new_path)) => {
+            let (text, locale) = languages.pick(&language, &record.text, &record.translations);
+            let speech = speech.add(&template_said(text, &this_ctx), &voice(languages, locale));
+            let recording_url = recording_url(callback_url, id, &record.name);
+            let opts = twiml::Record {
+                max_length: record.max_length,
+                finish_on_key: record.finish_on_key,
+                beep: record.beep,
+                transcribe_callback: if record.transcribe { Some(recording_url.clone()) } else { None },
+                status_callback: recording_url,
+            };
+            let twiml = twiml::record(&path_url(callback_url, script_name, id, new_path), &speech.segments, &opts);
+            Reply { twiml, said: speech.said, next_path: Some(new_path.clone()), handles_no_input: false, texts: Vec::new() }
+        }
+        // A Return that isn't inside a sub-flow has nowhere to go, and anything else left over (e.g. a
+        // Repeat used as a key's action) can't be said. Panicking would take every call down with it
+        _ => hangup(Speech::new(callback_url).add("Invalid path", &default_voice)),
+    };
+    reply.texts = texts;
+    Ok(Outcome::Reply(reply))
+}
+
+/// The language the call is in, a SetLanguage wins over the context variable
+fn call_language<T>(languages: &script::Languages, vars: &ctxmgr::CallVars<T>) -> String where T: ctxmgr::Context {
+    vars.session.language.clone()
+        .or_else(|| languages.context_var.as_ref().and_then(|var| vars.resolve_variable(var)).map(str::to_owned))
+        .unwrap_or_else(|| languages.default.clone())
+}
+
+fn voice(languages: &script::Languages, locale: &str) -> twiml::Voice {
+    twiml::Voice { name: languages.voice(locale).to_owned(), language: locale.to_owned() }
+}
+
+/// The caller's number, twilio calls it To when we started the call and From when they called us
+fn caller_number(body_params: &HashMap<String, String>) -> Option<String> {
+    let key = match body_params.get("Direction").map(String::as_ref) {
+        Some("outbound-api") | Some("outbound-dial") => "To",
+        _ => "From",
+    };
+    body_params.get(key).cloned()
+}
+
+
+/// The url twilio should send the next request for the node at path of the script called script_name to
+pub fn path_url(callback_url: &str, script_name: &str, id: i32, path: &str) -> String {
+    // Paths can have # and * in them, # would otherwise start the url's fragment
+    let encoded_path = url::form_urlencoded::byte_serialize(path.as_bytes()).collect::<String>();
+    let encoded_script = url::form_urlencoded::byte_serialize(script_name.as_bytes()).collect::<String>();
+    format!("{}?path={}&id={}&script={}", callback_url, encoded_path, id, encoded_script)
+}
+
+/// Where twilio sends the recording and transcription callbacks for the call's recording called name
+fn recording_url(callback_url: &str, id: i32, name: &str) -> String {
+    let encoded_name = url::form_urlencoded::byte_serialize(name.as_bytes()).collect::<String>();
+    format!("{}/recording?id={}&name={}", callback_url.trim_right_matches('/'), id, encoded_name)
+}
+
+/// What the caller hears, built up a piece at a time since each piece can be in a different language
+struct Speech<'a> {
+    callback_url: &'a str,
+    /// Audio files are still written as [play:...]
+    said: String,
+    segments: Vec<twiml::Segment>,
+}
+
+impl<'a> Speech<'a> {
+    fn new(callback_url: &'a str) -> Speech<'a> {
+        Speech { callback_url, said: String::new(), segments: Vec::new() }
+    }
+
+    fn add(mut self, text: &str, voice: &twiml::Voice) -> Self {
+        self.said += &ssml::unescape(text);
+        self.segments.extend(prompt::segments(text, self.callback_url, voice));
+        self
+    }
+}
+
+fn gather_input(new_url: &str, new_path: &str, speech: Speech, mut gather: twiml::Gather, handles_no_input: bool) -> Reply {
+    if handles_no_input {
+        gather.no_input_url = Some(format!("{}&no_input=true", new_url));
+    }
+    Reply { twiml: twiml::get_input(new_url, &speech.segments, &gather), said: speech.said, next_path: Some(new_path.to_owned()), handles_no_input, texts: Vec::new() }
+}
+
+fn hangup(speech: Speech) -> Reply {
+    Reply { twiml: twiml::say(&speech.segments), said: speech.said, next_path: None, handles_no_input: false, texts: Vec::new() }
+}
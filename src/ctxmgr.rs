@@ -9,21 +9,81 @@ pub trait Context {
 }
 
 
+/// Everything we keep track of for a call besides its Context
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Variables captured from what the caller entered, e.g. the digits of a Collect. They can
+    /// be used in templates and conditions just like the Context's variables
+    pub inputs: HashMap<String, String>,
+    /// Path of a script -> how many invalid inputs in a row the caller has given it
+    pub invalid_inputs: HashMap<String, u32>,
+    /// Path of a prompt -> how many times in a row it has timed out without any input
+    pub no_inputs: HashMap<String, u32>,
+    /// The name of the script the call is running and which version of it, set when the call starts
+    pub script: Option<String>,
+    pub script_version: Option<usize>,
+    /// The locale a SetLanguage switched the call to
+    pub language: Option<String>,
+    /// Name of each Split the call went through -> the variant it got
+    pub variants: HashMap<String, String>,
+    /// Set once the call reaches the end of the script, rather than the caller hanging up on the way or
+    /// the call ending after too many invalid inputs or timeouts
+    pub completed: bool,
+    pub recordings: Vec<Recording>,
+    /// Names of the Record actions the call has reached, recordings are only taken for these
+    pub recording_names: Vec<String>,
+}
+
+
+/// Something the caller recorded, e.g. a voicemail. Twilio tells us about the recording and its
+/// transcription in separate requests so the duration and transcription can show up later
+#[derive(Debug)]
+pub struct Recording {
+    /// The name of the Record action that made it
+    pub name: String,
+    pub url: String,
+    pub duration: Option<u32>,
+    pub transcription: Option<String>,
+}
+
+
+/// A call's Context together with the variables captured during the call, if both have a
+/// variable with the same name the Context wins
+pub struct CallVars<'a, CTX_T> where CTX_T : Context + 'a {
+    pub ctx: &'a CTX_T,
+    pub session: &'a Session,
+}
+
+impl<'a, CTX_T> CallVars<'a, CTX_T> where CTX_T : Context {
+    pub fn resolve_variable(&self, var: &str) -> Option<&'a str> {
+        self.ctx.resolve_variable(var).or_else(|| self.session.inputs.get(var).map(String::as_ref))
+    }
+
+    pub fn list_vars(&self) -> Vec<&'a str> {
+        let mut vars = self.ctx.list_vars().to_vec();
+        vars.extend(self.session.inputs.keys().map(String::as_ref).filter(|var| !self.ctx.list_vars().contains(var)));
+        vars
+    }
+}
+
+
 #[derive(Debug)]
 pub struct ContextManager<CTX_T> where CTX_T : Context {
     last_call_id: i32,
-    contexts: HashMap<i32, CTX_T>
+    contexts: HashMap<i32, CTX_T>,
+    sessions: HashMap<i32, Session>
 }
 
 impl<CTX_T> ContextManager<CTX_T> where CTX_T: Context + ::std::fmt::Debug {
 
     pub fn new() -> ContextManager<CTX_T> {
-        ContextManager { last_call_id: 0, contexts: HashMap::new() }
+        ContextManager { last_call_id: 0, contexts: HashMap::new(), sessions: HashMap::new() }
     }
     pub fn insert_context(&mut self, context: CTX_T) -> i32 {
         let this_call_id = self.last_call_id + 1;
         let res = self.contexts.insert(this_call_id, context);
         assert!(!res.is_some()); // The id shouldn't already exist in the table
+        self.sessions.insert(this_call_id, Session::default());
         self.last_call_id = this_call_id;
         this_call_id
     }
@@ -31,5 +91,193 @@ impl<CTX_T> ContextManager<CTX_T> where CTX_T: Context + ::std::fmt::Debug {
     pub fn load_context(&self,  c_id:i32) -> Option<&CTX_T> {
         self.contexts.get(&c_id)
     }
+
+    pub fn load_session(&self, c_id: i32) -> Option<&Session> {
+        self.sessions.get(&c_id)
+    }
+
+    pub fn load_call_vars(&self, c_id: i32) -> Option<CallVars<CTX_T>> {
+        match (self.contexts.get(&c_id), self.sessions.get(&c_id)) {
+            (Some(ctx), Some(session)) => Some(CallVars { ctx, session }),
+            _ => None,
+        }
+    }
+
+    /// None for an id that was never given a context, sessions are only created by insert_context
+    pub fn session_mut(&mut self, c_id: i32) -> Option<&mut Session> {
+        self.sessions.get_mut(&c_id)
+    }
+
+    /// Stores something the caller entered during the call, e.g. the digits of a Collect
+    pub fn store_input(&mut self, c_id: i32, name: &str, value: String) {
+        if let Some(session) = self.session_mut(c_id) {
+            session.inputs.insert(name.to_owned(), value);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn load_input(&self, c_id: i32, name: &str) -> Option<&str> {
+        self.load_session(c_id).and_then(|session| session.inputs.get(name)).map(String::as_ref)
+    }
+
+    /// Counts another invalid input for the script at path, returns how many there have been in a row
+    pub fn record_invalid_input(&mut self, c_id: i32, path: &str) -> u32 {
+        self.session_mut(c_id).map_or(0, |session| {
+            let count = session.invalid_inputs.entry(path.to_owned()).or_insert(0);
+            *count += 1;
+            *count
+        })
+    }
+
+    pub fn clear_invalid_inputs(&mut self, c_id: i32, path: &str) {
+        if let Some(session) = self.session_mut(c_id) {
+            session.invalid_inputs.remove(path);
+        }
+    }
+
+    /// Counts another timeout without input for the prompt at path, returns how many there have been in a row
+    pub fn record_no_input(&mut self, c_id: i32, path: &str) -> u32 {
+        self.session_mut(c_id).map_or(0, |session| {
+            let count = session.no_inputs.entry(path.to_owned()).or_insert(0);
+            *count += 1;
+            *count
+        })
+    }
+
+    pub fn clear_no_inputs(&mut self, c_id: i32, path: &str) {
+        if let Some(session) = self.session_mut(c_id) {
+            session.no_inputs.remove(path);
+        }
+    }
+
+    /// Notes that the call reached the Record action called name, so its recording can be taken
+    pub fn expect_recording(&mut self, c_id: i32, name: &str) {
+        if let Some(session) = self.session_mut(c_id) {
+            if !session.recording_names.iter().any(|n| n == name) {
+                session.recording_names.push(name.to_owned());
+            }
+        }
+    }
+
+    /// Finds the call's recording with that url, adding it if twilio hasn't told us about it yet.
+    /// None unless the call reached a Record action called name and the url isn't already another
+    /// recording's
+    pub fn recording_mut(&mut self, c_id: i32, name: &str, url: &str) -> Option<&mut Recording> {
+        let session = self.session_mut(c_id)?;
+        if !session.recording_names.iter().any(|n| n == name) {
+            return None;
+        }
+        let recordings = &mut session.recordings;
+        Some(match recordings.iter().position(|r| r.url == url) {
+            Some(i) if recordings[i].name != name => return None,
+            Some(i) => &mut recordings[i],
+            None => {
+                recordings.push(Recording { name: name.to_owned(), url: url.to_owned(), duration: None, transcription: None });
+                recordings.last_mut().unwrap()
+            }
+        })
+    }
+
+    /// Every recording made during any call as CSV, one row per recording
+    pub fn export_recordings_csv(&self) -> String {
+        let mut ids = self.sessions.keys().cloned().collect::<Vec<i32>>();
+        ids.sort();
+
+        let mut out = String::from("id,name,url,duration,transcription\n");
+        for id in ids {
+            for recording in self.sessions[&id].recordings.iter() {
+                let row = vec![
+                    id.to_string(),
+                    csv_field(&recording.name),
+                    csv_field(&recording.url),
+                    recording.duration.map_or(String::new(), |d| d.to_string()),
+                    csv_field(recording.transcription.as_ref().map_or("", String::as_ref)),
+                ];
+                out += &(row.join(",") + "\n");
+            }
+        }
+        out
+    }
+
+    /// How many calls got each variant of each Split and how many of them completed, as CSV
+    pub fn export_variants_csv(&self) -> String {
+        let mut counts: HashMap<(&str, &str, &str), (u32, u32)> = HashMap::new();
+        for session in self.sessions.values() {
+            let script = session.script.as_ref().map_or("", String::as_ref);
+            for (split, variant) in session.variants.iter() {
+                let count = counts.entry((script, split, variant)).or_insert((0, 0));
+                count.0 += 1;
+                if session.completed {
+                    count.1 += 1;
+                }
+            }
+        }
+        let mut keys = counts.keys().cloned().collect::<Vec<(&str, &str, &str)>>();
+        keys.sort();
+
+        let mut out = String::from("script,split,variant,calls,completed,completion_rate\n");
+        for key in keys {
+            let (calls, completed) = counts[&key];
+            let row = vec![
+                csv_field(key.0),
+                csv_field(key.1),
+                csv_field(key.2),
+                calls.to_string(),
+                completed.to_string(),
+                format!("{:.3}", completed as f64 / calls as f64),
+            ];
+            out += &(row.join(",") + "\n");
+        }
+        out
+    }
+
+    /// Every call as a row of CSV, with a column for each context variable and each variable
+    /// captured during any of the calls, then the variant of each Split it went through
+    pub fn export_csv(&self) -> String {
+        let mut ids = self.contexts.keys().cloned().collect::<Vec<i32>>();
+        ids.sort();
+
+        let ctx_vars = self.contexts.values().next().map_or(Vec::new(), |ctx| ctx.list_vars().to_vec());
+        let mut captured_vars = self.sessions.values()
+            .flat_map(|session| session.inputs.keys().map(String::as_ref))
+            .filter(|var| !ctx_vars.contains(var))
+            .collect::<Vec<&str>>();
+        captured_vars.sort();
+        captured_vars.dedup();
+        let mut splits = self.sessions.values()
+            .flat_map(|session| session.variants.keys().map(String::as_ref))
+            .collect::<Vec<&str>>();
+        splits.sort();
+        splits.dedup();
+
+        let mut header = vec!["id".to_owned(), "script".to_owned(), "script_version".to_owned(), "completed".to_owned()];
+        header.extend(ctx_vars.iter().chain(captured_vars.iter()).map(|var| var.to_string()));
+        header.extend(splits.iter().map(|split| format!("variant:{}", split)));
+        let mut out = header.iter().map(|h| csv_field(h)).collect::<Vec<String>>().join(",") + "\n";
+
+        for id in ids {
+            let call_vars = self.load_call_vars(id).unwrap();
+            let session = call_vars.session;
+            let mut row = vec![
+                id.to_string(),
+                csv_field(session.script.as_ref().map_or("", String::as_ref)),
+                session.script_version.map_or(String::new(), |v| v.to_string()),
+                session.completed.to_string(),
+            ];
+            row.extend(ctx_vars.iter().chain(captured_vars.iter()).map(|var| csv_field(call_vars.resolve_variable(var).unwrap_or(""))));
+            row.extend(splits.iter().map(|split| csv_field(session.variants.get(*split).map_or("", String::as_ref))));
+            out += &(row.join(",") + "\n");
+        }
+        out
+    }
 }
 
+
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace("\"", "\"\""))
+    }
+    else {
+        s.to_owned()
+    }
+}
@@ -0,0 +1,241 @@
+use script::{ScriptBase, Action, Edge, NEXT_STEP, THEN_STEP, ELSE_STEP, FALLBACK_STEP, OPEN_STEP, CLOSED_STEP, HOLIDAY_STEP,
+             SUBFLOW_STEP, RETURN_STEP, key_char, key_index};
+
+
+/// Draws a ScriptBase as a graph so phone trees can be reviewed without reading the Rust or JSON.
+/// Prompts are nodes, keys are edges, error and retry limit actions are dashed edges and jumps are
+/// drawn as edges to wherever they lead.
+pub fn to_dot(sb: &ScriptBase) -> String {
+    let graph = Graph::build(sb);
+    let mut out = String::from("digraph script {\n    node [fontname=\"Helvetica\"];\n");
+    for node in graph.nodes.iter() {
+        let shape = match node.shape {
+            Shape::Prompt => "box",
+            Shape::Decision => "diamond",
+            Shape::End => "ellipse",
+        };
+        out += &format!("    {} [shape={}, label=\"{}\"];\n", node.id, shape, dot_escape(&node.label));
+    }
+    for edge in graph.edges.iter() {
+        let style = if edge.dashed { ", style=dashed" } else { "" };
+        out += &format!("    {} -> {} [label=\"{}\"{}];\n", edge.from, edge.to, dot_escape(&edge.label), style);
+    }
+    out += "}\n";
+    out
+}
+
+pub fn to_mermaid(sb: &ScriptBase) -> String {
+    let graph = Graph::build(sb);
+    let mut out = String::from("graph TD\n");
+    for node in graph.nodes.iter() {
+        let label = mermaid_escape(&node.label);
+        out += &match node.shape {
+            Shape::Prompt => format!("    {}[\"{}\"]\n", node.id, label),
+            Shape::Decision => format!("    {}{{\"{}\"}}\n", node.id, label),
+            Shape::End => format!("    {}([\"{}\"])\n", node.id, label),
+        };
+    }
+    for edge in graph.edges.iter() {
+        let arrow = if edge.dashed { "-.->" } else { "-->" };
+        out += &format!("    {} {}|\"{}\"| {}\n", edge.from, arrow, mermaid_escape(&edge.label), edge.to);
+    }
+    out
+}
+
+
+enum Shape {
+    Prompt,
+    Decision,
+    End,
+}
+
+struct Node {
+    id: String,
+    label: String,
+    shape: Shape,
+}
+
+struct GraphEdge {
+    from: String,
+    to: String,
+    label: String,
+    dashed: bool,
+}
+
+struct Graph<'a> {
+    sb: &'a ScriptBase,
+    nodes: Vec<Node>,
+    edges: Vec<GraphEdge>,
+}
+
+impl<'a> Graph<'a> {
+    fn build(sb: &'a ScriptBase) -> Graph<'a> {
+        let mut graph = Graph { sb, nodes: Vec::new(), edges: Vec::new() };
+        graph.add_action(&sb.root, Some(""), "root".to_owned());
+        graph
+    }
+
+    /// Adds act and everything below it, path is None for nodes under an error action since
+    /// they can't be reached by a path. Returns the id of the node edges into act should point at
+    fn add_action(&mut self, act: &Action, path: Option<&str>, id: String) -> String {
+        let (label, shape) = match act {
+            &Action::ExecuteScript(ref script) => {
+                let heading = script.label.as_ref().map_or(String::new(), |l| format!("[{}] ", l));
+                (format!("{}{}", heading, script.text), Shape::Prompt)
+            }
+            &Action::Collect(ref collect) => (format!("Collect {}: {}", collect.save_as, collect.text), Shape::Prompt),
+            &Action::Branch(ref branch) => (format!("{}?", branch.condition), Shape::Decision),
+            &Action::Split(ref split) => (format!("Split {}", split.name), Shape::Decision),
+            &Action::SubFlow(ref sub) => (format!("Run {}", sub.name), Shape::Prompt),
+            &Action::Hours(ref hours) => (format!("Open? ({})", hours.schedule.time_zone.name()), Shape::Decision),
+            &Action::HangupWithMessage(ref hangup) => (format!("Hang up: {}", hangup.text), Shape::End),
+            &Action::Transfer(ref transfer) => (format!("Transfer to {}", transfer.to), Shape::Prompt),
+            &Action::Record(ref record) => (format!("Record {}: {}", record.name, record.text), Shape::Prompt),
+            &Action::SendText(ref text) => (format!("Text {}: {}", text.to.as_ref().map_or("caller", String::as_ref), text.text), Shape::Prompt),
+            &Action::SetLanguage(ref set) => (format!("Switch to {}", set.language), Shape::Prompt),
+            // The labels it can answer with are only known once it's called, so only next and fallback are drawn
+            &Action::Webhook(ref hook) => (format!("Ask {}", hook.url), Shape::Decision),
+            // Jumps and repeats aren't nodes of their own, the edge leading to them is drawn to their target instead
+            &Action::GoToAction(_) | &Action::GoToLabel(_) | &Action::Repeat | &Action::Return => return id,
+            &Action::Back | &Action::MainMenu | &Action::RepeatPrompt => return id,
+        };
+        self.nodes.push(Node { id: id.clone(), label, shape });
+
+        for (edge, child) in act.children() {
+            let (child_id, child_path, mut label, dashed) = match edge {
+                Edge::Step(c) => (format!("{}_{}", id, id_step(c)), path.map(|p| format!("{}{}", p, c)), step_label(act, c), false),
+                Edge::Error => (format!("{}_err", id), None, "invalid input".to_owned(), true),
+                Edge::RetriesExceeded => (format!("{}_retries", id), None, "too many invalid inputs".to_owned(), true),
+                Edge::NoInput => {
+                    let reprompts = act.no_input().map_or(0, |n| n.max_reprompts);
+                    (format!("{}_noinput", id), None, format!("no input after {} reprompts", reprompts), true)
+                }
+            };
+
+            match child {
+                &Action::GoToAction(_) | &Action::GoToLabel(_) => label += " (jump)",
+                &Action::Back => label += " (back)",
+                &Action::MainMenu => label += " (main menu)",
+                &Action::RepeatPrompt => label += " (repeat prompt)",
+                &Action::Return => label += " (return)",
+                _ => {}
+            }
+            // The prompt the caller is at when child runs, error actions don't move the caller
+            let prompt_path = match edge {
+                Edge::Step(_) => child_path.as_ref().map(|p| self.sb.prompt_above(p)),
+                _ => path.map(str::to_owned),
+            };
+            let target = match child {
+                &Action::Repeat => id.clone(),
+                &Action::Back | &Action::MainMenu | &Action::RepeatPrompt => match prompt_path {
+                    Some(prompt_path) => self.jump_target(&self.sb.nav_target(child, &prompt_path).unwrap()),
+                    None => self.missing_node(&child_id, "prompt, it's relative to an error action"),
+                },
+                // Under an error action the caller is still at the script, which is in the same flow
+                &Action::Return => match child_path.as_ref().map(String::as_ref).or(path).and_then(|p| self.return_target(p)) {
+                    Some(target) => self.jump_target(&target),
+                    None => self.missing_node(&child_id, "sub-flow to return from"),
+                },
+                &Action::GoToAction(ref target) => self.jump_target(target),
+                &Action::GoToLabel(ref label) => match self.sb.path_of_label(label) {
+                    Some(target) => self.jump_target(target),
+                    None => self.missing_node(&child_id, &format!("label {}", label)),
+                },
+                _ => self.add_action(child, child_path.as_ref().map(String::as_ref), child_id),
+            };
+            self.edges.push(GraphEdge { from: id.clone(), to: target, label, dashed });
+        }
+        id
+    }
+
+    /// Targets might not have been added yet, but the id add_action gives a node only depends on its path.
+    /// Jumps aren't nodes, so a target that's another jump is followed to wherever the call would end up
+    fn jump_target(&mut self, target: &str) -> String {
+        let sb = self.sb;
+        let mut target = target.to_owned();
+        let mut seen = Vec::new();
+        loop {
+            let next = match sb.node_at(&target) {
+                None => return self.missing_node(&format!("missing_{}", self.nodes.len()), &format!("path {:?}", target)),
+                Some(&Action::GoToAction(ref path)) => Some(path.clone()),
+                Some(&Action::GoToLabel(ref label)) => sb.path_of_label(label).map(str::to_owned),
+                Some(act @ &Action::Back) | Some(act @ &Action::MainMenu) | Some(act @ &Action::RepeatPrompt) => sb.nav_target(act, &sb.prompt_above(&target)),
+                Some(&Action::Return) => self.return_target(&target),
+                Some(_) => break,
+            };
+            match next {
+                Some(ref next) if seen.contains(next) => return self.missing_node(&format!("missing_{}", self.nodes.len()), &format!("target, the jumps loop back to {:?}", next)),
+                Some(next) => seen.push(::std::mem::replace(&mut target, next)),
+                None => return self.missing_node(&format!("missing_{}", self.nodes.len()), &format!("target for the jump at {:?}", target)),
+            }
+        }
+        target.chars().fold(String::from("root"), |id, c| format!("{}_{}", id, id_step(c)))
+    }
+
+    /// Where a Return at path ends up, a flow can return straight into another Return when it was
+    /// the last thing the flow above it did
+    fn return_target(&self, path: &str) -> Option<String> {
+        let mut target = self.sb.return_path(path);
+        while let Some(t) = target.clone() {
+            match self.sb.node_at(&t) {
+                Some(&Action::Return) => target = self.sb.return_path(&t),
+                _ => break,
+            }
+        }
+        target
+    }
+
+    fn missing_node(&mut self, id: &str, what: &str) -> String {
+        self.nodes.push(Node { id: id.to_owned(), label: format!("Missing {}", what), shape: Shape::End });
+        id.to_owned()
+    }
+}
+
+
+/// Node ids are built from the path, but * and # aren't allowed in DOT or Mermaid ids
+fn id_step(c: char) -> String {
+    match c {
+        '*' => "star".to_owned(),
+        '#' => "pound".to_owned(),
+        _ => c.to_string(),
+    }
+}
+
+fn step_label(act: &Action, c: char) -> String {
+    match (act, c) {
+        (&Action::Collect(_), NEXT_STEP) => "entered".to_owned(),
+        (&Action::Branch(_), THEN_STEP) => "yes".to_owned(),
+        (&Action::Branch(_), ELSE_STEP) => "no".to_owned(),
+        (&Action::Hours(_), OPEN_STEP) => "open".to_owned(),
+        (&Action::Hours(_), CLOSED_STEP) => "closed".to_owned(),
+        (&Action::Hours(_), HOLIDAY_STEP) => "holiday".to_owned(),
+        (&Action::SubFlow(_), SUBFLOW_STEP) => "runs".to_owned(),
+        (&Action::SubFlow(_), RETURN_STEP) => "returned".to_owned(),
+        (&Action::Split(ref split), _) => match key_index(c).and_then(|i| split.variants.get(i)) {
+            Some(variant) => format!("{} ({})", variant.name, variant.weight),
+            None => c.to_string(),
+        },
+        (&Action::Transfer(_), FALLBACK_STEP) => "no answer".to_owned(),
+        (&Action::Record(_), NEXT_STEP) => "recorded".to_owned(),
+        (&Action::SendText(_), NEXT_STEP) => "sent".to_owned(),
+        (&Action::Webhook(_), NEXT_STEP) => "answered".to_owned(),
+        (&Action::Webhook(_), FALLBACK_STEP) => "failed".to_owned(),
+        (&Action::SetLanguage(_), NEXT_STEP) => "switched".to_owned(),
+        (&Action::ExecuteScript(ref script), _) => {
+            let words = script.keywords().iter()
+                .filter(|&&(key, _)| key_char(key) == c)
+                .flat_map(|&(_, ref phrases)| phrases.iter().map(|p| format!("\"{}\"", p)))
+                .collect::<Vec<String>>();
+            if words.is_empty() { c.to_string() } else { format!("{} or {}", c, words.join(" or ")) }
+        }
+        _ => c.to_string(),
+    }
+}
+
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn mermaid_escape(s: &str) -> String {
+    s.replace('"', "#quot;").replace('|', "#124;").replace('\n', " ")
+}
@@ -0,0 +1,249 @@
+//! Business hours for Hours actions. A schedule is a time zone, the hours it's open on each day of
+//! the week and optionally a holiday file with the dates it's closed, one per line:
+//!
+//! ```text
+//! # Anything after the date or a # is ignored
+//! 2026-12-25 Christmas
+//! 2027-01-01
+//! ```
+//!
+//! The holiday file is read again whenever it changes so holidays can be added without reloading the
+//! script. Until then its dates are kept, each call only checks the file's modification time.
+
+extern crate chrono;
+extern crate chrono_tz;
+
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::Read;
+use std::time::SystemTime;
+
+use self::chrono::{DateTime, Datelike, NaiveDate, NaiveTime, Utc, Weekday};
+use self::chrono_tz::Tz;
+
+
+/// Where the current time comes from, so routing can be tried at any time without waiting for it
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Always says it's the same time
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// Parses times like "2026-12-25T10:00:00-05:00"
+pub fn parse_time(s: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(s.trim())
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|e| format!("{:?} isn't a time like 2026-12-25T10:00:00-05:00: {}", s, e))
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Open,
+    Closed,
+    Holiday,
+}
+
+#[derive(Debug)]
+pub struct Schedule {
+    pub time_zone: Tz,
+    /// Open from the first time until the second on that day, a day can be open more than once
+    pub weekly: Vec<(Weekday, NaiveTime, NaiveTime)>,
+    pub holidays_file: Option<String>,
+    /// The holiday file's dates and when the file was last modified at the time they were read
+    holidays_read: RefCell<Option<(SystemTime, Vec<NaiveDate>)>>,
+}
+
+impl Schedule {
+    pub fn new(time_zone: Tz) -> Schedule {
+        Schedule { time_zone, weekly: Vec::new(), holidays_file: None, holidays_read: RefCell::new(None) }
+    }
+
+    pub fn open(mut self, day: Weekday, from: NaiveTime, until: NaiveTime) -> Self {
+        self.weekly.push((day, from, until));
+        self
+    }
+
+    pub fn holidays(mut self, path: &str) -> Self {
+        self.holidays_file = Some(path.to_owned());
+        self
+    }
+
+    /// Whether it's open at now, in the schedule's time zone
+    pub fn status(&self, now: DateTime<Utc>) -> Status {
+        let local = now.with_timezone(&self.time_zone).naive_local();
+        if let Some(ref path) = self.holidays_file {
+            match self.is_holiday(path, local.date()) {
+                Ok(true) => return Status::Holiday,
+                Ok(false) => {}
+                Err(e) => println!("{}, going by the weekly hours", e),
+            }
+        }
+
+        let (day, time) = (local.weekday(), local.time());
+        if self.weekly.iter().any(|&(open_day, from, until)| open_day == day && from <= time && time < until) {
+            Status::Open
+        }
+        else {
+            Status::Closed
+        }
+    }
+
+    /// Whether date is in the holiday file at path, which is only read again once it's been modified
+    fn is_holiday(&self, path: &str, date: NaiveDate) -> Result<bool, String> {
+        let modified = fs::metadata(path).and_then(|meta| meta.modified())
+            .map_err(|e| format!("Couldn't read holiday file {:?}: {}", path, e))?;
+        if let Some((ref read_at, ref dates)) = *self.holidays_read.borrow() {
+            if *read_at == modified {
+                return Ok(dates.contains(&date));
+            }
+        }
+        let dates = load_holidays(path)?;
+        let holiday = dates.contains(&date);
+        *self.holidays_read.borrow_mut() = Some((modified, dates));
+        Ok(holiday)
+    }
+}
+
+
+pub fn load_holidays(path: &str) -> Result<Vec<NaiveDate>, String> {
+    let mut contents = String::new();
+    File::open(path).and_then(|mut f| f.read_to_string(&mut contents))
+        .map_err(|e| format!("Couldn't read holiday file {:?}: {}", path, e))?;
+
+    contents.lines().enumerate()
+        .filter_map(|(i, line)| line.split('#').next().unwrap().split_whitespace().next().map(|date| (i, date)))
+        .map(|(i, date)| {
+            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| format!("Line {} of holiday file {:?}: {:?} isn't a date like 2026-12-25", i + 1, path, date))
+        })
+        .collect()
+}
+
+/// A day like "mon" or a range of days like "mon-fri"
+pub fn parse_days(s: &str) -> Result<Vec<Weekday>, String> {
+    let parse_day = |day: &str| day.trim().parse::<Weekday>().map_err(|_| format!("{:?} isn't a day like mon or mon-fri", s));
+    let mut ends = s.splitn(2, '-');
+    let first = parse_day(ends.next().unwrap())?;
+    let last = match ends.next() {
+        Some(day) => parse_day(day)?,
+        None => first,
+    };
+
+    let mut days = vec![first];
+    while *days.last().unwrap() != last {
+        let next = days.last().unwrap().succ();
+        days.push(next);
+    }
+    Ok(days)
+}
+
+/// Opening hours like "09:00-17:00", or several separated by commas like "09:00-12:00, 13:00-17:00"
+pub fn parse_hours(s: &str) -> Result<Vec<(NaiveTime, NaiveTime)>, String> {
+    let parse_time = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| format!("{:?} isn't a time like 09:00", time.trim()));
+    s.split(',').map(|range| {
+        let mut ends = range.splitn(2, '-');
+        let from = parse_time(ends.next().unwrap())?;
+        let until = parse_time(ends.next().ok_or(format!("{:?} isn't a range like 09:00-17:00", range.trim()))?)?;
+        if until <= from {
+            return Err(format!("{:?} closes before it opens", range.trim()));
+        }
+        Ok((from, until))
+    }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Write;
+
+    use super::chrono::{DateTime, NaiveTime, Utc, Weekday};
+    use super::chrono_tz::America::New_York;
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        FixedClock(parse_time(time).unwrap()).now()
+    }
+
+    fn nine_to_five(days: &str) -> Schedule {
+        let mut schedule = Schedule::new(New_York);
+        for day in parse_days(days).unwrap() {
+            schedule = schedule.open(day, NaiveTime::from_hms(9, 0, 0), NaiveTime::from_hms(17, 0, 0));
+        }
+        schedule
+    }
+
+    #[test]
+    fn opens_and_closes_on_the_minute() {
+        let schedule = nine_to_five("mon-fri");
+        assert_eq!(schedule.status(at("2026-10-19T08:59:00-04:00")), Status::Closed);
+        assert_eq!(schedule.status(at("2026-10-19T09:00:00-04:00")), Status::Open);
+        assert_eq!(schedule.status(at("2026-10-19T16:59:00-04:00")), Status::Open);
+        assert_eq!(schedule.status(at("2026-10-19T17:00:00-04:00")), Status::Closed);
+    }
+
+    #[test]
+    fn goes_by_the_schedules_time_zone() {
+        // 13:00 UTC is 09:00 in New York
+        assert_eq!(nine_to_five("mon").status(at("2026-10-19T13:00:00Z")), Status::Open);
+        assert_eq!(nine_to_five("mon").status(at("2026-10-19T12:59:00Z")), Status::Closed);
+    }
+
+    #[test]
+    fn day_ranges_wrap_around_the_week() {
+        assert_eq!(parse_days("fri-mon").unwrap(), vec![Weekday::Fri, Weekday::Sat, Weekday::Sun, Weekday::Mon]);
+        let schedule = nine_to_five("fri-mon");
+        assert_eq!(schedule.status(at("2026-10-18T10:00:00-04:00")), Status::Open); // Sunday
+        assert_eq!(schedule.status(at("2026-10-19T10:00:00-04:00")), Status::Open); // Monday
+        assert_eq!(schedule.status(at("2026-10-20T10:00:00-04:00")), Status::Closed); // Tuesday
+    }
+
+    #[test]
+    fn follows_daylight_saving_time() {
+        // Clocks in New York went forward on 2026-03-08, so 09:00 is 13:00 UTC that day and 14:00 UTC the day before
+        let schedule = nine_to_five("sat-sun");
+        assert_eq!(schedule.status(at("2026-03-07T13:30:00Z")), Status::Closed);
+        assert_eq!(schedule.status(at("2026-03-07T14:00:00Z")), Status::Open);
+        assert_eq!(schedule.status(at("2026-03-08T13:00:00Z")), Status::Open);
+        assert_eq!(schedule.status(at("2026-03-08T12:30:00Z")), Status::Closed);
+    }
+
+    #[test]
+    fn holidays_win_over_the_weekly_hours() {
+        let path = ::std::env::temp_dir().join(format!("twilio_2_holidays_{}.txt", ::std::process::id()));
+        File::create(&path).and_then(|mut f| f.write_all(b"# Closed\n2026-12-25 Christmas\n")).unwrap();
+        let schedule = nine_to_five("mon-fri").holidays(path.to_str().unwrap());
+        let christmas = schedule.status(at("2026-12-25T10:00:00-05:00"));
+        let christmas_eve = schedule.status(at("2026-12-24T10:00:00-05:00"));
+        ::std::fs::remove_file(&path).unwrap();
+        assert_eq!(christmas, Status::Holiday);
+        assert_eq!(christmas_eve, Status::Open);
+    }
+
+    #[test]
+    fn bad_days_and_hours_are_errors() {
+        assert!(parse_days("funday").is_err());
+        assert!(parse_days("mon-someday").is_err());
+        assert!(parse_hours("9am-5pm").is_err());
+        assert!(parse_hours("09:00").is_err());
+        assert!(parse_hours("17:00-09:00").is_err());
+        assert!(parse_hours("09:00-12:00, 12:00").is_err());
+        assert_eq!(parse_hours("09:00-12:00, 13:00-17:00").unwrap().len(), 2);
+    }
+}
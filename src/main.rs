@@ -4,15 +4,33 @@ extern crate futures;
 extern crate regex;
 extern crate hyper;
 extern crate url;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
 
 mod twil_api;
 mod script;
+mod script_loader;
+mod validate;
+mod script_versions;
+mod diagram;
+mod call_flow;
+mod webhook;
+mod hours;
+mod prompt;
+mod ssml;
+mod simulator;
 mod ctxmgr;
 mod twiml;
 mod responses;
 
 use std::collections::HashMap;
 use futures::{Future, Stream};
+use futures::future::Loop;
+
+/// The environment variable holding the shared secret for the admin routes, sent as
+/// `Authorization: Bearer <token>`
+const ADMIN_TOKEN_VAR: &'static str = "TWILIO_2_ADMIN_TOKEN";
 
 
 
@@ -47,23 +65,13 @@ impl ctxmgr::Context for ExampleUserContext {
 }
 
 
-fn template<T>(raw: &str, ctx: &T) -> String where T: ctxmgr::Context {
-    let mut owned_copy = String::from(raw);
-    for var_name in ctx.list_vars().iter() {
-        let to_replace = format!("{{{}}}", var_name);
-        let substitution:&str = ctx.resolve_variable(var_name).expect("CTX was unable to resolve variable");
-        owned_copy = owned_copy.replace(&to_replace, substitution);
-    }
-    owned_copy
-}
-
-
-
-
 struct TwilioResponseService<T> where T : ctxmgr::Context {
-    sb_ptr: std::rc::Rc<script::ScriptBase>,
+    scripts_ptr: std::rc::Rc<std::cell::RefCell<script_versions::ScriptRegistry>>,
     ctx_ptr: std::rc::Rc<std::cell::RefCell<ctxmgr::ContextManager<T>>>, // This could/should be RwLock a if multithreaded
-    pub_url: String
+    twilio_ptr: std::rc::Rc<twil_api::Twilio>,
+    evt_handle: tokio_core::reactor::Handle,
+    pub_url: String,
+    admin_token: Option<String>
 }
 
 impl<T> TwilioResponseService<T> where T: ctxmgr::Context + std::fmt::Debug + 'static {
@@ -73,10 +81,12 @@ impl<T> TwilioResponseService<T> where T: ctxmgr::Context + std::fmt::Debug + 's
         if uri.query().is_none() {
             return Box::new(futures::future::ok(responses::bad_request_error("Missing uri query")));
         }
-        let sb_ptr_clone = std::rc::Rc::clone(&self.sb_ptr);
+        let scripts_ptr_clone = std::rc::Rc::clone(&self.scripts_ptr);
         let ctx_ptr_clone = std::rc::Rc::clone(&self.ctx_ptr);
+        let twilio_ptr_clone = std::rc::Rc::clone(&self.twilio_ptr);
+        let handle_clone = self.evt_handle.clone();
         let url_clone = self.pub_url.clone();
-        let result = Box::new(body.concat2().and_then(move |bytes_vec| {
+        let result = Box::new(body.concat2().and_then(move |bytes_vec| -> <Self as hyper::server::Service>::Future {
             let qs = uri.query().unwrap();
             let qs_parsed_kvs = url::form_urlencoded::parse(qs.as_bytes()).into_owned().collect::<HashMap<String, String>>();// Todo this copy & alloc can be avoided
 
@@ -84,66 +94,153 @@ impl<T> TwilioResponseService<T> where T: ctxmgr::Context + std::fmt::Debug + 's
             let opt_id = qs_parsed_kvs.get("id");
 
             if opt_path.is_none() || opt_id.is_none() {
-                return futures::future::ok(responses::bad_request_error("Missing path or id"));
+                return Box::new(futures::future::ok(responses::bad_request_error("Missing path or id")));
             }
 
 
             let body_params = url::form_urlencoded::parse(&bytes_vec[..]).into_owned().collect::<HashMap<String, String>>();
-            let str_opt_digits = body_params.get("Digits");
-            let mut digits = None;
-
-            if let Some(dig) = str_opt_digits {
-                let parse_result = dig.parse::<i32>();
-
-                digits = match parse_result {
-                    Err(_) => return futures::future::ok(responses::bad_request_error("Couldn't parse digits")),
-                    Ok(parsed) => {
-                        if parsed > 9 || parsed < 0 {
-                            return futures::future::ok(responses::bad_request_error("Digits should be a single character only!"))
-                        }
-                        Some(parsed)
-                    }
-                };
-            }
-
 
             let (path_str, id_str) = (opt_path.unwrap(), opt_id.unwrap());
 
             let res_id_i32 = id_str.parse::<i32>();
             if res_id_i32.is_err() {
-                return futures::future::ok(responses::bad_request_error("Couldn't parse id"));
+                return Box::new(futures::future::ok(responses::bad_request_error("Couldn't parse id")));
             }
             let id_i32 = res_id_i32.unwrap();
+            // Ids come from /make_call, anything else isn't a call we started
+            if ctx_ptr_clone.borrow().load_context(id_i32).is_none() {
+                return Box::new(futures::future::ok(responses::bad_request_error("Unknown call id")));
+            }
 
+            let res_script_name = scripts_ptr_clone.borrow().resolve(qs_parsed_kvs.get("script").map(String::as_ref));
+            if let Err(e) = res_script_name {
+                return Box::new(futures::future::ok(responses::bad_request_error(&e)));
+            }
+            let script_name = res_script_name.unwrap();
+
+            // The first request of a call decides which script and which version of it the call gets for the rest of the call
+            let latest_version = scripts_ptr_clone.borrow().get(&script_name).unwrap().latest_version();
+            let version = {
+                let mut ctx_mgr = ctx_ptr_clone.borrow_mut();
+                let session = ctx_mgr.session_mut(id_i32).expect("Sessions are inserted along with their context");
+                if *session.script.get_or_insert_with(|| script_name.clone()) != script_name {
+                    return Box::new(futures::future::ok(responses::bad_request_error("The call is running a different script")));
+                }
+                *session.script_version.get_or_insert(latest_version)
+            };
+            let opt_sb = scripts_ptr_clone.borrow().get(&script_name).unwrap().get(version);
+            if opt_sb.is_none() {
+                return Box::new(futures::future::ok(responses::bad_request_error("Unknown script version")));
+            }
+            let sb_ptr_clone = opt_sb.unwrap();
+
+            // Sent by the <Redirect> after a <Gather> that timed out
+            let no_input = qs_parsed_kvs.get("no_input").map_or(false, |v| v == "true");
+
+            let first = call_flow::respond(&sb_ptr_clone, &ctx_ptr_clone, id_i32, path_str, no_input, &body_params, &url_clone, &script_name, &hours::SystemClock);
+            // Webhooks are waited on without holding up other calls, and each answer can lead to another webhook
+            Box::new(futures::future::loop_fn(first, move |outcome| -> Box<Future<Item=Loop<hyper::Response, Result<call_flow::Outcome, &'static str>>, Error=hyper::Error>> {
+                match outcome {
+                    Ok(call_flow::Outcome::Reply(reply)) => {
+                        send_texts(&twilio_ptr_clone, &handle_clone, reply.texts);
+                        Box::new(futures::future::ok(Loop::Break(hyper::Response::from(reply.twiml))))
+                    }
+                    Ok(call_flow::Outcome::Webhook(call)) => {
+                        println!("Asking {} about call {}", call.url, id_i32);
+                        let sb = std::rc::Rc::clone(&sb_ptr_clone);
+                        let ctx_ptr = std::rc::Rc::clone(&ctx_ptr_clone);
+                        let url = url_clone.clone();
+                        let script_name = script_name.clone();
+                        Box::new(webhook::post(&twilio_ptr_clone, &handle_clone, &call.url, call.body.clone(), call.timeout).then(move |answer| {
+                            Ok(Loop::Continue(call_flow::webhook_done(&sb, &ctx_ptr, id_i32, call, answer, &url, &script_name, &hours::SystemClock)))
+                        }))
+                    }
+                    Err(e) => Box::new(futures::future::ok(Loop::Break(responses::bad_request_error(e)))),
+                }
+            }))
+        }));
+        result
+    }
 
-            let new_path = format!("{}{}", path_str, digits.map_or("".to_owned(), |x|format!("{}", x)));
-            let desired_action = sb_ptr_clone.follow_path(&new_path);
-
-            println!("The desired action is {:?}", desired_action);
-            println!("{:?}", *ctx_ptr_clone.borrow());
-
+    /// Starts a call to ?phone= with the rest of the query as its context, running ?script=
+    fn handle_make_call(&self, req: hyper::Request) -> <Self as hyper::server::Service>::Future {
+        let parsed_kvs = url::form_urlencoded::parse(req.query().unwrap_or("").as_bytes()).into_owned().collect::<HashMap<String, String>>();
 
+        {
+            let required_keys = ["f_name", "l_name", "phone"];
+            let given_keys = parsed_kvs.keys().map(String::as_ref).collect::<Vec<&str>>();
+            if !required_keys.iter().all(|k:&&str| given_keys.contains(k)) {
+                return Box::new(futures::future::ok(responses::bad_request_error("Missing some required_keys")))
+            }
+        }
 
+        // Leaving out script is fine when the server only runs one
+        let script_name = match self.scripts_ptr.borrow().resolve(parsed_kvs.get("script").map(String::as_ref)) {
+            Ok(name) => name,
+            Err(e) => return Box::new(futures::future::ok(responses::bad_request_error(&e))),
+        };
+        let phone = parsed_kvs["phone"].clone();
+
+        let id = self.ctx_ptr.borrow_mut().insert_context(T::from_kvs(parsed_kvs));
+        self.ctx_ptr.borrow_mut().session_mut(id).expect("Sessions are inserted along with their context").script = Some(script_name.clone());
+
+        println!("Calling {} with script {}, call id {}", phone, script_name, id);
+        let callback_url = call_flow::path_url(&self.pub_url, &script_name, id, "");
+        self.evt_handle.spawn(self.twilio_ptr.start_call(&phone, &callback_url).then(move |result| {
+            if let Err(e) = result {
+                println!("Couldn't start call {}: {:?}", id, e);
+            }
+            Ok(())
+        }));
+        Box::new(futures::future::ok(hyper::Response::new().with_body(format!("Started call {} with script {}", id, script_name))))
+    }
 
-            let ctx_mgr_ref = ctx_ptr_clone.borrow();
+    /// Whether req carries `Authorization: Bearer <token>` with the token from ADMIN_TOKEN_VAR. Without
+    /// the variable set nobody is let in
+    fn is_admin(&self, req: &hyper::Request) -> bool {
+        use hyper::header::{Authorization, Bearer};
+        match (self.admin_token.as_ref(), req.headers().get::<Authorization<Bearer>>()) {
+            (Some(token), Some(&Authorization(ref bearer))) => &bearer.token == token,
+            _ => false,
+        }
+    }
 
-            let this_ctx = ctx_mgr_ref.load_context(id_i32).unwrap(); // TODO UNWRAP
+    /// Twilio's recordingStatusCallback and transcribeCallback for a Record action, they come in
+    /// separately from the call's flow once the recording or transcription is ready
+    fn handle_recording(&self, req: hyper::Request) -> <Self as hyper::server::Service>::Future {
+        let qs_parsed_kvs = url::form_urlencoded::parse(req.query().unwrap_or("").as_bytes()).into_owned().collect::<HashMap<String, String>>();
+        let opt_id = qs_parsed_kvs.get("id").and_then(|id| id.parse::<i32>().ok());
+        let opt_name = qs_parsed_kvs.get("name").cloned();
+        if opt_id.is_none() || opt_name.is_none() {
+            return Box::new(futures::future::ok(responses::bad_request_error("Missing id or name")));
+        }
+        let (id_i32, name) = (opt_id.unwrap(), opt_name.unwrap());
+        if self.ctx_ptr.borrow().load_context(id_i32).is_none() {
+            return Box::new(futures::future::ok(responses::bad_request_error("Unknown call id")));
+        }
 
+        let ctx_ptr_clone = std::rc::Rc::clone(&self.ctx_ptr);
+        Box::new(req.body().concat2().map(move |bytes_vec| {
+            let body_params = url::form_urlencoded::parse(&bytes_vec[..]).into_owned().collect::<HashMap<String, String>>();
+            let opt_url = body_params.get("RecordingUrl");
+            if opt_url.is_none() {
+                return responses::bad_request_error("Missing RecordingUrl");
+            }
 
-            // These should really all require a hmac
-            match desired_action {
-                Some((&script::Action::ExecuteScript(ref script), ref new_path)) => {
-                    let new_url = format!("{}?path={}&id={}", url_clone, new_path, id_i32);
-                    futures::future::ok(hyper::Response::from(twiml::get_input(&new_url, &template(&script.text, this_ctx))))
-                }
-                Some((&script::Action::HangupWithMessage(ref msg), ref new_path)) => {
-                    futures::future::ok(hyper::Response::from(twiml::say(msg)))
-                }
-                None => futures::future::ok(hyper::Response::from(twiml::say("Invalid path"))),
-                _ => panic!("Found some path I don't know how to parse"),
+            let mut ctx_mgr = ctx_ptr_clone.borrow_mut();
+            let recording = match ctx_mgr.recording_mut(id_i32, &name, opt_url.unwrap()) {
+                Some(recording) => recording,
+                None => return responses::bad_request_error("The call has no recording by that name"),
+            };
+            if let Some(duration) = body_params.get("RecordingDuration").and_then(|d| d.parse::<u32>().ok()) {
+                recording.duration = Some(duration);
             }
-        }));
-        result
+            if let Some(text) = body_params.get("TranscriptionText") {
+                recording.transcription = Some(text.clone());
+            }
+            println!("Recording for call {}: {:?}", id_i32, recording);
+            hyper::Response::new()
+        }))
     }
 }
 
@@ -166,6 +263,29 @@ impl<T> hyper::server::Service for TwilioResponseService<T> where T : ctxmgr::Co
 
 
     fn call(&self, req: Self::Request) -> Self::Future {
+        if req.method() == &hyper::Method::Post && req.path() == "/admin/reload" {
+            if !self.is_admin(&req) {
+                return Box::new(futures::future::ok(responses::unauthorized_error("Missing or wrong admin token")));
+            }
+            let parsed_kvs = url::form_urlencoded::parse(req.query().unwrap_or("").as_bytes()).into_owned().collect::<HashMap<String, String>>();
+            let mut scripts = self.scripts_ptr.borrow_mut();
+            let reload_result = scripts.resolve(parsed_kvs.get("script").map(String::as_ref))
+                .and_then(|name| scripts.get_mut(&name).unwrap().reload().map(|version| (name, version)));
+            println!("Reloading script: {:?}", reload_result);
+            return Box::new(futures::future::ok(match reload_result {
+                Ok((name, version)) => hyper::Response::new().with_body(format!("New calls will use version {} of script {}", version, name)),
+                Err(e) => responses::bad_request_error(&e),
+            }));
+        }
+        if req.method() == &hyper::Method::Post && req.path() == "/recording" {
+            return self.handle_recording(req);
+        }
+        if req.method() == &hyper::Method::Post && req.path() == "/make_call" {
+            if !self.is_admin(&req) {
+                return Box::new(futures::future::ok(responses::unauthorized_error("Missing or wrong admin token")));
+            }
+            return self.handle_make_call(req);
+        }
         if req.method() == &hyper::Method::Post {
             return self.handle_twilio(req);
         }
@@ -173,48 +293,84 @@ impl<T> hyper::server::Service for TwilioResponseService<T> where T : ctxmgr::Co
 
         println!("{:?} {:?}", req.method(), req.path());
         println!("QUERY = {:?}", req.query());
-        if req.method() == &hyper::Method::Get && req.path() == "/make_call" {
-            let parsed_kvs = url::form_urlencoded::parse(req.query().unwrap_or("").as_bytes()).into_owned().collect::<HashMap<String, String>>();
-
-            {
-                let required_keys = ["f_name", "l_name", "phone"];
-                let given_keys = parsed_kvs.keys().map(String::as_ref).collect::<Vec<&str>>();
-                if !required_keys.iter().all(|k:&&str| given_keys.contains(k)) {
-                    return Box::new(futures::future::ok(responses::bad_request_error("Missing some required_keys")))
-                }
-            }
-
-//            let phone_cp = String::from(parsed_kvs.get("phone"));
-
-
-            self.ctx_ptr.borrow_mut().insert_context(T::from_kvs(parsed_kvs));
+        // The exports have what callers entered, said and recorded in them
+        let exports = ["/results", "/results/variants", "/recordings"];
+        if req.method() == &hyper::Method::Get && exports.contains(&req.path()) && !self.is_admin(&req) {
+            return Box::new(futures::future::ok(responses::unauthorized_error("Missing or wrong admin token")));
+        }
+        if req.method() == &hyper::Method::Get && req.path() == "/results" {
+            return Box::new(futures::future::ok(responses::csv(self.ctx_ptr.borrow().export_csv())));
+        }
+        if req.method() == &hyper::Method::Get && req.path() == "/results/variants" {
+            return Box::new(futures::future::ok(responses::csv(self.ctx_ptr.borrow().export_variants_csv())));
+        }
+        if req.method() == &hyper::Method::Get && req.path().starts_with("/audio/") {
+            return Box::new(futures::future::ok(serve_audio(&req.path()["/audio/".len()..])));
+        }
+        if req.method() == &hyper::Method::Get && req.path() == "/recordings" {
+            return Box::new(futures::future::ok(responses::csv(self.ctx_ptr.borrow().export_recordings_csv())));
+        }
+        Box::new(futures::future::ok(responses::bad_request_error("method/path not supported")))
+    }
+}
 
-//            let mut evt_loop = tokio_core::reactor::Core::new().unwrap();
-//            let twilio_client = twil_api::Twilio::new(&evt_loop.handle());
-//
-//            twilio_client.start_call()
-//
-//            println!("{:?}", self.ctx_ptr.borrow());
 
+/// Texts are sent in the background, the call doesn't wait for them or care if they fail
+fn send_texts(twilio: &twil_api::Twilio, evt_handle: &tokio_core::reactor::Handle, texts: Vec<call_flow::OutgoingText>) {
+    for text in texts {
+        println!("Texting {}: {:?}", text.to, text.body);
+        let to = text.to.clone();
+        evt_handle.spawn(twilio.send_text_message(&text.to, &text.body).then(move |result| {
+            if let Err(e) = result {
+                println!("Couldn't send text to {}: {:?}", to, e);
+            }
+            Ok(())
+        }));
+    }
+}
 
-        }
-        Box::new(futures::future::ok(responses::bad_request_error("method/path not supported")))
+/// Recorded prompts played with [play:file], see prompt.rs
+fn serve_audio(file: &str) -> hyper::Response {
+    use std::io::Read;
+    let mut bytes = Vec::new();
+    let read_result = prompt::local_path(file)
+        .ok_or(std::io::Error::new(std::io::ErrorKind::InvalidInput, "bad file name"))
+        .and_then(|path| std::fs::File::open(path))
+        .and_then(|mut f| f.read_to_end(&mut bytes));
+    if let Err(e) = read_result {
+        println!("Couldn't serve audio file {:?}: {}", file, e);
+        return responses::not_found_error("No such audio file");
     }
+
+    let content_type = match file.rsplit('.').next() {
+        Some("mp3") => "audio/mpeg",
+        Some("wav") => "audio/wav",
+        Some("ogg") => "audio/ogg",
+        _ => "application/octet-stream",
+    };
+    responses::audio(bytes, content_type)
 }
 
 
 struct ServiceMaker<CTX_T> where CTX_T : ctxmgr::Context {
     ctx_mgr_ptr: std::rc::Rc<std::cell::RefCell<ctxmgr::ContextManager<CTX_T>>>,
-    script_base_ptr: std::rc::Rc<script::ScriptBase>,
-    pub_url: String
+    scripts_ptr: std::rc::Rc<std::cell::RefCell<script_versions::ScriptRegistry>>,
+    twilio_ptr: std::rc::Rc<twil_api::Twilio>,
+    evt_handle: tokio_core::reactor::Handle,
+    pub_url: String,
+    admin_token: Option<String>
 }
 
 impl<CTX_T> ServiceMaker<CTX_T> where CTX_T : ctxmgr::Context {
-    fn new(script_base: script::ScriptBase, ctx_mgr: ctxmgr::ContextManager<CTX_T>, url: String) -> ServiceMaker<CTX_T> {
+    fn new(scripts: script_versions::ScriptRegistry, ctx_mgr: ctxmgr::ContextManager<CTX_T>, twilio: twil_api::Twilio,
+           evt_handle: tokio_core::reactor::Handle, url: String, admin_token: Option<String>) -> ServiceMaker<CTX_T> {
         ServiceMaker {
             pub_url: url,
-            script_base_ptr: std::rc::Rc::new(script_base),
-            ctx_mgr_ptr: std::rc::Rc::new(std::cell::RefCell::new(ctx_mgr))
+            admin_token,
+            scripts_ptr: std::rc::Rc::new(std::cell::RefCell::new(scripts)),
+            ctx_mgr_ptr: std::rc::Rc::new(std::cell::RefCell::new(ctx_mgr)),
+            twilio_ptr: std::rc::Rc::new(twilio),
+            evt_handle,
         }
     }
 }
@@ -226,7 +382,14 @@ impl<CTX_T> hyper::server::NewService for ServiceMaker<CTX_T> where CTX_T : ctxm
     type Instance = TwilioResponseService<CTX_T>;
 
     fn new_service(&self) -> Result<Self::Instance, std::io::Error> {
-        Ok(TwilioResponseService {pub_url: self.pub_url.clone(), sb_ptr: std::rc::Rc::clone(&self.script_base_ptr), ctx_ptr: std::rc::Rc::clone(&self.ctx_mgr_ptr)})
+        Ok(TwilioResponseService {
+            pub_url: self.pub_url.clone(),
+            scripts_ptr: std::rc::Rc::clone(&self.scripts_ptr),
+            ctx_ptr: std::rc::Rc::clone(&self.ctx_mgr_ptr),
+            twilio_ptr: std::rc::Rc::clone(&self.twilio_ptr),
+            evt_handle: self.evt_handle.clone(),
+            admin_token: self.admin_token.clone(),
+        })
     }
 }
 
@@ -257,12 +420,118 @@ fn launch_ngrok(evt_handle: &tokio_core::reactor::Handle) -> impl Future<Item=St
 
 
 
-fn main() {
+/// The name and file of a script given as `name=script.json` or `script.json`
+fn script_arg(arg: &str) -> (String, Option<String>) {
+    let mut parts = arg.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(path)) => (name.to_owned(), Some(path.to_owned())),
+        _ => {
+            let stem = std::path::Path::new(arg).file_stem().map_or(arg.to_owned(), |s| s.to_string_lossy().into_owned());
+            (stem, Some(arg.to_owned()))
+        }
+    }
+}
+
+/// Loads the script file if one was given, otherwise returns the built in example script
+fn load_script(path: Option<&String>) -> script::ScriptBase {
+    match path {
+        Some(path) => script_loader::load_file(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => example_script(),
+    }
+}
+
+fn example_script() -> script::ScriptBase {
+    use script::{ScriptBase, Script, Collect, Action, Hangup, NoInput, STAR_KEY};
+    ScriptBase::from_root(
+        Script::with_text("Hello {f_name}, please press 1 or 2")
+            .on(1, Action::ExecuteScript(Script::with_text("You pressed 1, now press 3 or 4, or star to go back")
+                .on(3, Action::HangupWithMessage(Hangup::with_text("You pressed 1-3")))
+                .on(4, Action::HangupWithMessage(Hangup::with_text("You pressed 1-4")))
+                .on(5, Action::GoToAction("2".to_owned()))
+                .on(STAR_KEY, Action::Back)
+            ))
+            .on(2, Action::HangupWithMessage(Hangup::with_text("You pressed 2")))
+            .on(3, Action::Collect(Collect::with_text("Please enter your 5 digit zip code", "zip",
+                Action::HangupWithMessage(Hangup::with_text("Thanks, you entered {zip}. Goodbye"))).digits(5)))
+            .err_message("Sorry, that isn't one of the options.")
+            .max_retries(3, Action::HangupWithMessage(Hangup::with_text("Goodbye")))
+            .on_no_input(NoInput::new(2, Action::HangupWithMessage(Hangup::with_text("Goodbye"))).message("Sorry, we didn't hear anything."))
+    )
+}
 
 
+fn main() {
 
+    let args = std::env::args().collect::<Vec<String>>();
+
+    // `twilio_2 diagram dot|mermaid [script.json]` prints the script as a graph instead of serving it
+    if args.get(1).map(String::as_ref) == Some("diagram") {
+        let script_base = load_script(args.get(3));
+        match args.get(2).map(String::as_ref) {
+            Some("dot") => print!("{}", diagram::to_dot(&script_base)),
+            Some("mermaid") => print!("{}", diagram::to_mermaid(&script_base)),
+            _ => {
+                eprintln!("Usage: twilio_2 diagram dot|mermaid [script.json]");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
+    // `twilio_2 simulate [--live-webhooks] [script.json] [context.json]` walks the script in the terminal, see simulator.rs
+    if args.get(1).map(String::as_ref) == Some("simulate") {
+        let live_webhooks = args.iter().any(|arg| arg == "--live-webhooks");
+        let args = args.into_iter().filter(|arg| arg != "--live-webhooks").collect::<Vec<String>>();
+        let (script_name, script_file) = args.get(2).map_or(("example".to_owned(), None), |arg| script_arg(arg));
+        let script_base = load_script(script_file.as_ref());
+        let ctx = match args.get(3) {
+            Some(path) => <ExampleUserContext as ctxmgr::Context>::from_kvs(simulator::load_context_kvs(path).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            })),
+            None => ExampleUserContext { f_name : "will".to_owned(), l_name : "keat".to_owned()},
+        };
+        let problems = validate::validate(&script_base, ctxmgr::Context::list_vars(&ctx));
+        for problem in problems.iter() {
+            println!("{}", problem);
+        }
+        if problems.iter().any(|p| p.severity == validate::Severity::Error) {
+            eprintln!("Refusing to simulate, the script has errors");
+            std::process::exit(1);
+        }
+        simulator::run(&script_base, &script_name, ctx, live_webhooks);
+        return;
+    }
 
+    // Any number of scripts can be given as `name=script.json`, or just `script.json` to name it after
+    // the file. Without any the built in example script is served as "example"
+    let script_args = if args.len() > 1 { args[1..].iter().map(|arg| script_arg(arg)).collect() } else { vec![("example".to_owned(), None)] };
+
+    let sample_ctx = ExampleUserContext { f_name : "will".to_owned(), l_name : "keat".to_owned()};
+    let ctx_vars = ctxmgr::Context::list_vars(&sample_ctx);
+    let mut scripts = script_versions::ScriptRegistry::new();
+    let mut any_errors = false;
+    for &(ref name, ref script_file) in script_args.iter() {
+        if scripts.get(name).is_some() {
+            eprintln!("There's more than one script called {:?}", name);
+            std::process::exit(1);
+        }
+        let script_base = load_script(script_file.as_ref());
+        let problems = validate::validate(&script_base, ctx_vars);
+        for problem in problems.iter() {
+            println!("{}: {}", name, problem);
+        }
+        any_errors |= problems.iter().any(|p| p.severity == validate::Severity::Error);
+        // POST /admin/reload?script=name with the admin token loads the script file again for new calls
+        scripts.insert(name, script_versions::ScriptVersions::new(script_base, script_file.clone(), ctx_vars));
+    }
+    if any_errors {
+        eprintln!("Refusing to start, a script has errors");
+        std::process::exit(1);
+    }
 
 
     let mut evt_loop = tokio_core::reactor::Core::new().unwrap();
@@ -279,30 +548,24 @@ fn main() {
 
 
 
-    use script::{ScriptBase, Script, Action};
-    let script_base = ScriptBase::from_root(
-        Script::with_text("Hello {f_name}, please press 1 or 2")
-            .on(1, Action::ExecuteScript(Script::with_text("You pressed 1, now press 3 or 4")
-                .on(3, Action::HangupWithMessage("You pressed 1-3".to_owned()))
-                .on(4, Action::HangupWithMessage("You pressed 1-4".to_owned()))
-                .on(5, Action::GoToAction("2".to_owned()))
-            ))
-            .on(2, Action::HangupWithMessage("You pressed 2".to_owned()))
-    );
-
     let mut context_mgr = ctxmgr::ContextManager::<ExampleUserContext>::new();
-    context_mgr.insert_context(ExampleUserContext { f_name : "will".to_owned(), l_name : "keat".to_owned()});
+    context_mgr.insert_context(sample_ctx);
 
 
 
-    let call_future = twilio_client.start_call("+12038324888", &format!("{}?path=&id={}", pub_url, 1));
+    let call_future = twilio_client.start_call("+12038324888", &call_flow::path_url(&pub_url, &script_args[0].0, 1, ""));
     //let sjv = evt_loop.run(call_future).unwrap();
 //    println!("sjv = {:?}", sjv);
 
     let ip = "0.0.0.0:80".parse().unwrap();
 
+    let admin_token = std::env::var(ADMIN_TOKEN_VAR).ok().and_then(|token| if token.is_empty() { None } else { Some(token) });
+    if admin_token.is_none() {
+        println!("{} isn't set, the admin routes will refuse everyone", ADMIN_TOKEN_VAR);
+    }
+
 
-    let server = hyper::server::Http::new().serve_addr_handle(&ip, &handle,  ServiceMaker::new(script_base, context_mgr, String::from(pub_url))).unwrap();
+    let server = hyper::server::Http::new().serve_addr_handle(&ip, &handle,  ServiceMaker::new(scripts, context_mgr, twilio_client, handle.clone(), String::from(pub_url), admin_token)).unwrap();
 
     println!("Starting server....");
 
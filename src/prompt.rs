@@ -0,0 +1,80 @@
+//! Prompts can mix recorded audio with text to speech. `[play:intro.mp3]` plays a file from the
+//! audio directory, which the server serves at /audio/, and `[play:https://...]` plays a file from
+//! anywhere. `[play:intro.mp3|Welcome to Acme]` says the text after the | instead if the file is
+//! missing. Everything outside the brackets is spoken as usual, so
+//! `"[play:hello.mp3] {f_name}, [play:menu.mp3|please press 1 or 2]"` plays a recorded hello,
+//! says the caller's name and then plays the recorded menu.
+
+extern crate regex;
+
+use std::path::PathBuf;
+
+use twiml::{Segment, Voice};
+
+
+/// Where local audio files are looked up, relative to the working directory
+pub const AUDIO_DIR: &'static str = "audio";
+
+
+/// A piece of a prompt as written in the script
+#[derive(Debug, PartialEq)]
+pub enum Part<'a> {
+    Text(&'a str),
+    Audio { file: &'a str, fallback: Option<&'a str> },
+}
+
+pub fn parts(text: &str) -> Vec<Part> {
+    let re = regex::Regex::new(r"\[play:([^\]|]*)(?:\|([^\]]*))?\]").unwrap();
+    let mut parts = Vec::new();
+    let mut last_end = 0;
+    for cap in re.captures_iter(text) {
+        let whole = cap.get(0).unwrap();
+        if whole.start() > last_end {
+            parts.push(Part::Text(&text[last_end..whole.start()]));
+        }
+        parts.push(Part::Audio { file: cap.get(1).unwrap().as_str().trim(), fallback: cap.get(2).map(|m| m.as_str()) });
+        last_end = whole.end();
+    }
+    if last_end < text.len() {
+        parts.push(Part::Text(&text[last_end..]));
+    }
+    parts
+}
+
+/// Files that aren't urls are served from AUDIO_DIR
+pub fn is_local(file: &str) -> bool {
+    !file.contains("://")
+}
+
+/// Where a local audio file is on disk, None if the name could point outside AUDIO_DIR
+pub fn local_path(file: &str) -> Option<PathBuf> {
+    if file.is_empty() || file.starts_with('/') || file.contains("..") || file.contains('\\') {
+        return None;
+    }
+    Some(PathBuf::from(AUDIO_DIR).join(file))
+}
+
+/// Whether a local audio file can actually be played, urls are assumed to be fine
+pub fn audio_exists(file: &str) -> bool {
+    !is_local(file) || local_path(file).map_or(false, |path| path.is_file())
+}
+
+/// Turns an already templated prompt into what twilio should say and play, base_url is where
+/// twilio can reach our server. Text is said with voice
+pub fn segments(text: &str, base_url: &str, voice: &Voice) -> Vec<Segment> {
+    parts(text).into_iter().filter_map(|part| match part {
+        Part::Text(text) => Some(Segment::Say(text.to_owned(), voice.clone())),
+        Part::Audio { file, fallback } => {
+            if !is_local(file) {
+                Some(Segment::Play(file.to_owned()))
+            }
+            else if audio_exists(file) {
+                Some(Segment::Play(format!("{}/audio/{}", base_url.trim_right_matches('/'), file)))
+            }
+            else {
+                println!("Audio file {:?} is missing, falling back to {:?}", file, fallback);
+                fallback.map(|text| Segment::Say(text.to_owned(), voice.clone()))
+            }
+        }
+    }).collect()
+}
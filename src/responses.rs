@@ -1,7 +1,8 @@
 extern crate hyper;
 extern crate futures;
 
-use hyper::header::ContentLength;
+use hyper::mime;
+use hyper::header::{ContentLength, ContentType};
 
 pub fn not_allowed_error(text: &str) -> hyper::Response{
     hyper::Response::new()
@@ -10,6 +11,20 @@ pub fn not_allowed_error(text: &str) -> hyper::Response{
         .with_body(String::from(text))
 }
 
+pub fn unauthorized_error(text: &str) -> hyper::Response {
+    hyper::Response::new()
+        .with_status(hyper::StatusCode::Unauthorized)
+        .with_header(ContentLength(text.len() as u64))
+        .with_body(String::from(text))
+}
+
+pub fn not_found_error(text: &str) -> hyper::Response {
+    hyper::Response::new()
+        .with_status(hyper::StatusCode::NotFound)
+        .with_header(ContentLength(text.len() as u64))
+        .with_body(String::from(text))
+}
+
 pub fn bad_request_error(text: &str) -> hyper::Response {
     hyper::Response::new()
         .with_status(hyper::StatusCode::BadRequest)
@@ -17,3 +32,16 @@ pub fn bad_request_error(text: &str) -> hyper::Response {
         .with_body(String::from(text))
 }
 
+pub fn csv(text: String) -> hyper::Response {
+    hyper::Response::new()
+        .with_header(ContentType(mime::TEXT_CSV))
+        .with_header(ContentLength(text.len() as u64))
+        .with_body(text)
+}
+
+pub fn audio(bytes: Vec<u8>, content_type: &str) -> hyper::Response {
+    hyper::Response::new()
+        .with_header(ContentType(content_type.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM)))
+        .with_header(ContentLength(bytes.len() as u64))
+        .with_body(bytes)
+}
@@ -1,23 +1,237 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use hours::Schedule;
+
 #[derive(Debug)]
 
 pub enum Action {
     ExecuteScript(Script),
-    HangupWithMessage(String),
+    Collect(Collect),
+    HangupWithMessage(Hangup),
     GoToAction(String),
+    /// Jumps to the node with that label, unlike GoToAction it keeps working when menus are reordered
+    GoToLabel(String),
+    /// Goes down one of two sub-trees depending on the call's context, the caller doesn't hear anything
+    Branch(Branch),
     Repeat,
+    /// Goes back to the prompt above the one the caller is at, e.g. "press star to go back"
+    Back,
+    /// Goes back to the root of the script
+    MainMenu,
+    /// Says the prompt the caller is at again. Unlike Repeat it can be used anywhere, e.g. "press 9 to hear this again"
+    RepeatPrompt,
+    /// Connects the caller to a person
+    Transfer(Transfer),
+    /// Records a message from the caller, e.g. a voicemail
+    Record(Record),
+    /// Sends an SMS without the caller hearing anything, then goes on to its next action
+    SendText(TextMessage),
+    /// Asks another system what to do next, e.g. looking up an order's status, without the caller hearing anything
+    Webhook(Webhook),
+    /// Switches the call to another language, e.g. after "press 2 for Spanish", then goes on to its next action
+    SetLanguage(SetLanguage),
+    /// Goes down one of its sub-trees depending on whether it's within business hours, the caller doesn't hear anything
+    Hours(Hours),
+    /// Sends each call down one of several weighted variants, e.g. to compare two wordings of a prompt
+    Split(Split),
+    /// Runs a sub-flow, e.g. a date of birth check shared by several menus, then goes on to its next action
+    SubFlow(SubFlow),
+    /// Ends the sub-flow it's in and goes on to the action after the SubFlow that ran it
+    Return,
+}
+
+/// Path step taken out of a Collect or Record once its input has been stored, out of a SendText
+/// once the text is sent, out of a Webhook that answered without picking a label, or out of a
+/// SetLanguage. It's not a key so it can't be confused with a menu choice
+pub const NEXT_STEP: char = 'n';
+
+/// Path steps taken out of a Branch when its condition holds or doesn't
+pub const THEN_STEP: char = 't';
+pub const ELSE_STEP: char = 'e';
+
+/// Path step taken out of a Transfer when nobody answered, or out of a Webhook that failed
+pub const FALLBACK_STEP: char = 'f';
+
+/// Path steps taken out of an Hours action when it's open, closed or a holiday
+pub const OPEN_STEP: char = 'o';
+pub const CLOSED_STEP: char = 'c';
+pub const HOLIDAY_STEP: char = 'h';
+
+/// Path steps into a SubFlow's flow and, once the flow returns, on to its next action
+pub const SUBFLOW_STEP: char = 's';
+pub const RETURN_STEP: char = 'r';
+
+/// Indexes for the star and pound keys, they come after 0-9 wherever a key is a usize
+pub const STAR_KEY: usize = 10;
+pub const POUND_KEY: usize = 11;
+pub const NUM_KEYS: usize = 12;
+
+/// The character a key is written as in paths and twilio's Digits, '*' and '#' for STAR_KEY and POUND_KEY
+pub fn key_char(key: usize) -> char {
+    match key {
+        STAR_KEY => '*',
+        POUND_KEY => '#',
+        _ => ::std::char::from_digit(key as u32, 10).unwrap(),
+    }
+}
+
+/// The opposite of key_char, None if c isn't a key on the keypad
+pub fn key_index(c: char) -> Option<usize> {
+    match c {
+        '*' => Some(STAR_KEY),
+        '#' => Some(POUND_KEY),
+        _ => c.to_digit(10).map(|d| d as usize),
+    }
+}
+
+/// How an action is reached from the action above it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    /// A step in the path, either a key press or one of the *_STEP constants
+    Step(char),
+    /// The script's error action, it doesn't get a step in the path of its own
+    Error,
+    /// What the script does once its retry limit is hit, no step in the path either
+    RetriesExceeded,
+    /// What a prompt does once the caller hasn't said anything too many times, no step in the path either
+    NoInput,
+}
+
+impl Action {
+    /// The actions directly below this one in the tree
+    pub fn children(&self) -> Vec<(Edge, &Action)> {
+        match self {
+            &Action::ExecuteScript(ref script) => {
+                let mut children = script.other_scripts.iter().enumerate()
+                    .filter_map(|(key, act_opt)| act_opt.as_ref().map(|act| (Edge::Step(key_char(key)), act)))
+                    .collect::<Vec<(Edge, &Action)>>();
+                children.push((Edge::Error, &script.err));
+                if let Some((_, ref act)) = script.retry_limit {
+                    children.push((Edge::RetriesExceeded, act));
+                }
+                if let Some(ref no_input) = script.no_input {
+                    children.push((Edge::NoInput, &no_input.then));
+                }
+                children
+            }
+            &Action::Collect(ref collect) => {
+                let mut children = vec![(Edge::Step(NEXT_STEP), &*collect.next)];
+                if let Some((_, ref act)) = collect.retry_limit {
+                    children.push((Edge::RetriesExceeded, act));
+                }
+                if let Some(ref no_input) = collect.no_input {
+                    children.push((Edge::NoInput, &no_input.then));
+                }
+                children
+            }
+            &Action::Branch(ref branch) => vec![(Edge::Step(THEN_STEP), &branch.then), (Edge::Step(ELSE_STEP), &branch.otherwise)],
+            &Action::Transfer(ref transfer) => vec![(Edge::Step(FALLBACK_STEP), &transfer.fallback)],
+            &Action::Record(ref record) => vec![(Edge::Step(NEXT_STEP), &record.next)],
+            &Action::SendText(ref text) => vec![(Edge::Step(NEXT_STEP), &text.next)],
+            &Action::Webhook(ref hook) => vec![(Edge::Step(NEXT_STEP), &hook.next), (Edge::Step(FALLBACK_STEP), &hook.fallback)],
+            &Action::SetLanguage(ref set) => vec![(Edge::Step(NEXT_STEP), &set.next)],
+            &Action::Hours(ref hours) => {
+                let mut children = vec![(Edge::Step(OPEN_STEP), &*hours.open), (Edge::Step(CLOSED_STEP), &*hours.closed)];
+                if let Some(ref holiday) = hours.holiday {
+                    children.push((Edge::Step(HOLIDAY_STEP), &**holiday));
+                }
+                children
+            }
+            // Each variant is reached through the key of its index, though nobody presses it
+            &Action::Split(ref split) => split.variants.iter().enumerate()
+                .filter(|&(i, _)| i < NUM_KEYS)
+                .map(|(i, variant)| (Edge::Step(key_char(i)), &variant.act))
+                .collect(),
+            &Action::SubFlow(ref sub) => vec![(Edge::Step(SUBFLOW_STEP), &*sub.flow), (Edge::Step(RETURN_STEP), &sub.next)],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The name the caller's input is saved under, if this action saves it
+    pub fn save_as(&self) -> Option<&str> {
+        match self {
+            &Action::ExecuteScript(ref script) => script.save_as.as_ref().map(String::as_ref),
+            &Action::Collect(ref collect) => Some(&collect.save_as),
+            _ => None,
+        }
+    }
+
+    /// Whether the action carries on from its own path, by waiting for input there, being reported
+    /// back to it or going through one of its steps. Error, retry limit and no input actions don't
+    /// have a path so these can only be reached from there through a jump
+    pub fn has_own_steps(&self) -> bool {
+        match self {
+            &Action::ExecuteScript(_) | &Action::Collect(_) | &Action::Record(_) | &Action::Transfer(_) | &Action::SendText(_)
+            | &Action::Webhook(_) | &Action::SetLanguage(_) | &Action::Split(_) | &Action::SubFlow(_) | &Action::Branch(_)
+            | &Action::Hours(_) => true,
+            _ => false,
+        }
+    }
+
+    /// What happens when the caller doesn't press or say anything, only prompts have this
+    pub fn no_input(&self) -> Option<&NoInput> {
+        match self {
+            &Action::ExecuteScript(ref script) => script.no_input.as_ref(),
+            &Action::Collect(ref collect) => collect.no_input.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Said before whatever comes after input the prompt can't use, along with its translations
+    pub fn err_message(&self) -> Option<(&str, &HashMap<String, String>)> {
+        match self {
+            &Action::ExecuteScript(ref script) => script.err_message.as_ref().map(|msg| (msg.as_ref(), &script.err_translations)),
+            &Action::Collect(ref collect) => collect.err_message.as_ref().map(|msg| (msg.as_ref(), &collect.err_translations)),
+            _ => None,
+        }
+    }
+
+    /// Whether attempts invalid inputs in a row are more than the prompt allows
+    pub fn retries_exceeded(&self, attempts: u32) -> bool {
+        match self {
+            &Action::ExecuteScript(ref script) => script.retries_exceeded(attempts),
+            &Action::Collect(ref collect) => collect.retries_exceeded(attempts),
+            _ => false,
+        }
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            &Action::ExecuteScript(ref script) => script.label.as_ref().map(String::as_ref),
+            &Action::Collect(ref collect) => collect.label.as_ref().map(String::as_ref),
+            _ => None,
+        }
+    }
 }
 
 
 #[derive(Debug)]
 pub struct ScriptBase {
-    pub root: Action
+    pub root: Action,
+    pub languages: Languages,
+    /// Label -> path of the labeled node
+    labels: HashMap<String, String>
 }
 
 impl ScriptBase {
 
     pub fn from_root(root: Script) -> ScriptBase {
+        let root = Action::ExecuteScript(root);
+        let mut labels = HashMap::new();
+        find_labels(&root, String::new(), &mut labels);
+
+        ScriptBase { root, languages: Languages::default(), labels }
+    }
+
+    pub fn with_languages(mut self, languages: Languages) -> Self {
+        self.languages = languages;
+        self
+    }
 
-        ScriptBase { root: Action::ExecuteScript(root) }
+    pub fn path_of_label(&self, label: &str) -> Option<&str> {
+        self.labels.get(label).map(String::as_ref)
     }
 
     /// Returns the action at that path and the new path, new path exists because
@@ -25,29 +239,32 @@ impl ScriptBase {
     /// to adjust your path
     pub fn follow_path(&self, path: &str) -> Option<(&Action, String)> {
         let mut cur: &Action = &self.root;
-        for c in path.chars() {
-            let dig_opt = c.to_digit(10);
+        for (i, c) in path.char_indices() {
+            // Anything but a script is left through one of the *_STEP constants rather than a key
+            if let &Action::ExecuteScript(_) = cur {} else {
+                match cur.children().into_iter().find(|&(edge, _)| edge == Edge::Step(c)) {
+                    Some((_, next)) => {
+                        cur = next;
+                        continue;
+                    }
+                    None => return None,
+                }
+            }
+
+            let key_opt = key_index(c);
 
-            if dig_opt.is_none() { return None }
+            if key_opt.is_none() { return None }
 
-            let digit = dig_opt.unwrap();
+            let key = key_opt.unwrap();
 
 
             match cur {
                  &Action::ExecuteScript(ref script_ref) => {
-                     let act_opt = &script_ref.other_scripts[digit as usize];
+                     let act_opt = &script_ref.other_scripts[key];
                      if act_opt.is_none() {
-
-                         match *script_ref.err {
-                             Action::Repeat => {
-                                 // When you take a wrong turn down a path, and hit the action Action::Repeat
-                                 // you want to remove the last digit from the path so that path no longer
-                                 // contains the invalid turn
-                                 return Some((cur, path.clone().chars().into_iter().take(path.len()-1).collect::<String>()))
-                             },
-                             _=> return Some((&script_ref.err, String::from(path)))
-                         }
-
+                         // The caller stays at the script, so a Repeat drops the invalid turn from the
+                         // path and jumps and navigation are relative to the script
+                         return self.follow_stepless(&script_ref.err, cur, path[..i].to_owned());
                      }
                      cur = act_opt.as_ref().unwrap();
 
@@ -59,11 +276,140 @@ impl ScriptBase {
             }
         }
 
-        if let &Action::GoToAction(ref new_path) = cur {
-            self.follow_path(new_path)
+        self.follow_jumps(cur, path)
+    }
+
+    /// If act is a GoToAction, GoToLabel or navigation action returns wherever it leads, otherwise
+    /// returns act itself. path is where act is in the tree
+    fn follow_jumps<'a>(&'a self, act: &'a Action, path: &str) -> Option<(&'a Action, String)> {
+        match act {
+            &Action::GoToAction(ref new_path) => self.follow_path(new_path),
+            &Action::GoToLabel(ref label) => match self.path_of_label(label) {
+                Some(new_path) => self.follow_path(new_path),
+                None => None,
+            },
+            &Action::Back | &Action::MainMenu | &Action::RepeatPrompt => {
+                let prompt_path = self.prompt_above(path);
+                self.follow_path(&self.nav_target(act, &prompt_path).unwrap())
+            }
+            _ => Some((act, String::from(path))),
+        }
+    }
+
+    /// Where a Back, MainMenu or RepeatPrompt leads when the caller is at the prompt at prompt_path,
+    /// None for any other action
+    pub fn nav_target(&self, act: &Action, prompt_path: &str) -> Option<String> {
+        match act {
+            &Action::Back => Some(self.prompt_above(prompt_path)),
+            &Action::MainMenu => Some(String::new()),
+            &Action::RepeatPrompt => Some(prompt_path.to_owned()),
+            _ => None,
+        }
+    }
+
+    /// Path of the closest node above path that asks the caller for something, Branches, Hours,
+    /// Splits, sub-flows, texts, webhooks and language changes are skipped since the caller never hears them. The root has nothing above it
+    /// so it's its own
+    pub fn prompt_above(&self, path: &str) -> String {
+        let mut above = path.to_owned();
+        above.pop();
+        loop {
+            match self.node_at(&above) {
+                Some(&Action::Branch(_)) | Some(&Action::Hours(_)) | Some(&Action::Split(_)) | Some(&Action::SubFlow(_)) | Some(&Action::SendText(_)) | Some(&Action::Webhook(_))
+                    | Some(&Action::SetLanguage(_)) if !above.is_empty() => { above.pop(); }
+                _ => return above,
+            }
+        }
+    }
+
+    /// Paths of the SubFlows the node at path is inside of, innermost last. A flow's path starts with
+    /// the path of the SubFlow that ran it and returning leaves it, so the path twilio sends back with
+    /// every request is the call's stack
+    pub fn call_stack(&self, path: &str) -> Vec<String> {
+        path.char_indices()
+            .filter(|&(i, c)| c == SUBFLOW_STEP && match self.node_at(&path[..i]) {
+                Some(&Action::SubFlow(_)) => true,
+                _ => false,
+            })
+            .map(|(i, _)| path[..i].to_owned())
+            .collect()
+    }
+
+    /// Where a Return at path goes, None if it isn't inside a sub-flow
+    pub fn return_path(&self, path: &str) -> Option<String> {
+        self.call_stack(path).pop().map(|call| format!("{}{}", call, RETURN_STEP))
+    }
+
+    /// Returns the action at exactly that path, unlike follow_path it doesn't fall back to error
+    /// actions or follow GoToActions
+    pub fn node_at(&self, path: &str) -> Option<&Action> {
+        let mut cur: &Action = &self.root;
+        for c in path.chars() {
+            match cur.children().into_iter().find(|&(edge, _)| edge == Edge::Step(c)) {
+                Some((_, next)) => cur = next,
+                None => return None,
+            }
+        }
+        Some(cur)
+    }
+
+    /// What happens when the script at path gets input it has no action for (a key with no action,
+    /// or speech that matched none of its keywords) for the attempts-th time in a row. It's the
+    /// script's error action until its retry limit is passed, then the retry limit's action.
+    pub fn follow_invalid_input(&self, path: &str, attempts: u32) -> Option<(&Action, String)> {
+        match self.follow_path(path) {
+            Some((cur, cur_path)) => match cur {
+                &Action::ExecuteScript(ref script_ref) => {
+                    let err_act: &Action = match script_ref.retry_limit {
+                        Some((_, ref exceeded_act)) if script_ref.retries_exceeded(attempts) => exceeded_act,
+                        _ => &script_ref.err,
+                    };
+                    self.follow_stepless(err_act, cur, cur_path)
+                },
+                // A Collect has no error action of its own, it asks again until its retry limit
+                &Action::Collect(ref collect) => match collect.retry_limit {
+                    Some((_, ref exceeded_act)) if collect.retries_exceeded(attempts) => self.follow_stepless(exceeded_act, cur, cur_path),
+                    _ => Some((cur, cur_path)),
+                },
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    /// What happens when the prompt at path has timed out without any input for the attempts-th
+    /// time in a row. The prompt is said again until its no input limit is passed, then the
+    /// no input action is run.
+    pub fn follow_no_input(&self, path: &str, attempts: u32) -> Option<(&Action, String)> {
+        match self.follow_path(path) {
+            Some((cur, cur_path)) => match cur.no_input() {
+                Some(no_input) if no_input.exceeded(attempts) => self.follow_stepless(&no_input.then, cur, cur_path),
+                _ => Some((cur, cur_path)),
+            },
+            None => None,
+        }
+    }
+
+    /// Runs an action that doesn't have a step of its own (an error or no input action) of the
+    /// prompt cur, the caller is still at cur_path
+    fn follow_stepless<'a>(&'a self, act: &'a Action, cur: &'a Action, cur_path: String) -> Option<(&'a Action, String)> {
+        match act {
+            &Action::Repeat => Some((cur, cur_path)),
+            &Action::Back | &Action::MainMenu | &Action::RepeatPrompt => self.follow_path(&self.nav_target(act, &cur_path).unwrap()),
+            _ => self.follow_jumps(act, &cur_path),
         }
-        else {
-            Some((cur, String::from(path)))
+    }
+}
+
+/// Error actions don't have a path of their own so labels inside them are skipped, if the same
+/// label is used twice the first one found wins
+fn find_labels(act: &Action, path: String, labels: &mut HashMap<String, String>) {
+    if let Some(label) = act.label() {
+        labels.entry(label.to_owned()).or_insert_with(|| path.clone());
+    }
+    for (edge, child) in act.children() {
+        if let Edge::Step(c) = edge {
+            find_labels(child, format!("{}{}", path, c), labels);
         }
     }
 }
@@ -71,9 +417,49 @@ impl ScriptBase {
 #[derive(Debug)]
 
 pub struct Script {
+    /// In the script's default language
     pub text: String,
+    /// Locale -> the text in that language
+    pub translations: HashMap<String, String>,
+    pub label: Option<String>,
+    /// If set, whatever key the caller pressed or whatever they said is saved under this name
+    pub save_as: Option<String>,
+    pub input: Input,
+    /// Speech results with a lower confidence than this are treated as invalid input
+    pub min_confidence: f32,
+    /// Said before the error action is run, e.g. "Sorry, that isn't one of the options"
+    pub err_message: Option<String>,
+    /// Locale -> the err_message in that language
+    pub err_translations: HashMap<String, String>,
     err: Box<Action>,
-    other_scripts: Vec<Option<Action>>
+    /// How many invalid inputs in a row are allowed and what to do after that, e.g. hang up
+    retry_limit: Option<(u32, Box<Action>)>,
+    /// Seconds twilio waits for the caller to start pressing keys or talking
+    pub timeout: u32,
+    /// Without this the call ends when the caller doesn't say anything
+    pub no_input: Option<NoInput>,
+    other_scripts: Vec<Option<Action>>,
+    keywords: Vec<(usize, Vec<String>)>,
+    /// Locale -> the keywords callers in that language say instead
+    keyword_translations: HashMap<String, Vec<(usize, Vec<String>)>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    Dtmf,
+    Speech,
+    DtmfSpeech,
+}
+
+impl Input {
+    /// The value of the input attribute on a twiml <Gather>
+    pub fn twiml_name(&self) -> &'static str {
+        match *self {
+            Input::Dtmf => "dtmf",
+            Input::Speech => "speech",
+            Input::DtmfSpeech => "dtmf speech",
+        }
+    }
 }
 
 
@@ -81,69 +467,657 @@ impl Script {
 
     pub fn with_text(s: &str) -> Script {
         let default_err_option = Box::new(Action::Repeat);
-        Script { text: String::from(s), other_scripts: (0..10).map(|_| None ).collect() , err: default_err_option}
+        Script {
+            text: String::from(s),
+            translations: HashMap::new(),
+            label: None,
+            save_as: None,
+            input: Input::Dtmf,
+            min_confidence: 0.5,
+            other_scripts: (0..NUM_KEYS).map(|_| None ).collect(),
+            err_message: None,
+            err_translations: HashMap::new(),
+            err: default_err_option,
+            retry_limit: None,
+            timeout: DEFAULT_TIMEOUT,
+            no_input: None,
+            keywords: Vec::new(),
+            keyword_translations: HashMap::new(),
+        }
+    }
+
+    /// Gives this script a name GoToLabel can jump to
+    pub fn labeled(mut self, label: &str) -> Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    /// What the text is in another language, e.g. `.translated("es-MX", "Hola, presione 1 o 2")`
+    pub fn translated(mut self, locale: &str, text: &str) -> Self {
+        self.translations.insert(locale.to_owned(), text.to_owned());
+        self
     }
 
+    pub fn retries_exceeded(&self, attempts: u32) -> bool {
+        match self.retry_limit {
+            Some((max_retries, _)) => attempts > max_retries,
+            None => false,
+        }
+    }
+
+    pub fn save_input_as(mut self, name: &str) -> Self {
+        self.save_as = Some(name.to_owned());
+        self
+    }
+
+    pub fn listen_for(mut self, input: Input) -> Self {
+        self.input = input;
+        self
+    }
+
+    /// Saying any of these words or phrases is the same as pressing key
+    pub fn on_words(mut self, key: usize, words: &[&str]) -> Self {
+        self.keywords.push((key, words.iter().map(|w| w.to_lowercase()).collect()));
+        self
+    }
+
+    /// Like on_words for callers in locale, e.g. `.on_words_in("es-MX", 1, &["facturación"])`
+    pub fn on_words_in(mut self, locale: &str, key: usize, words: &[&str]) -> Self {
+        let words = words.iter().map(|w| w.to_lowercase()).collect();
+        self.keyword_translations.entry(locale.to_owned()).or_insert_with(Vec::new).push((key, words));
+        self
+    }
+
+    pub fn min_confidence(mut self, confidence: f32) -> Self {
+        self.min_confidence = confidence;
+        self
+    }
+
+    /// Returns the key whose keywords in locale appear in what the caller said, if any
+    pub fn match_speech(&self, speech: &str, confidence: f32, locale: &str) -> Option<usize> {
+        if confidence < self.min_confidence {
+            return None;
+        }
+
+        // Pad with spaces so only whole words match, "no" shouldn't match "know"
+        let words = speech.to_lowercase().chars()
+            .map(|c| if c.is_alphanumeric() || c == '\'' { c } else { ' ' })
+            .collect::<String>();
+        let padded = format!(" {} ", words.split_whitespace().collect::<Vec<&str>>().join(" "));
+
+        self.keywords_in(locale).iter()
+            .find(|&&(_, ref phrases)| phrases.iter().any(|p| padded.contains(&format!(" {} ", p))))
+            .map(|&(key, _)| key)
+    }
+
+    /// The keys the caller can choose by voice and the words or phrases that choose them
+    pub fn keywords(&self) -> &[(usize, Vec<String>)] {
+        &self.keywords
+    }
+
+    /// Locale -> the keys the caller can choose by voice in that language and the words that choose them
+    pub fn keyword_translations(&self) -> &HashMap<String, Vec<(usize, Vec<String>)>> {
+        &self.keyword_translations
+    }
+
+    /// The language speech is recognized in for a call in language, it's default unless the
+    /// keywords are translated to language
+    pub fn listens_in<'a>(&self, language: &'a str, default: &'a str) -> &'a str {
+        if self.keyword_translations.contains_key(language) { language } else { default }
+    }
+
+    /// The keywords for speech recognized in locale
+    pub fn keywords_in(&self, locale: &str) -> &[(usize, Vec<String>)] {
+        self.keyword_translations.get(locale).map_or(&self.keywords[..], |keywords| &keywords[..])
+    }
+
+    /// All the words and phrases this script listens for in locale, given to twilio as hints
+    pub fn all_keywords(&self, locale: &str) -> Vec<&str> {
+        self.keywords_in(locale).iter().flat_map(|&(_, ref phrases)| phrases.iter().map(String::as_ref)).collect()
+    }
+
+    /// key is 0-9, STAR_KEY or POUND_KEY
     pub fn on(mut self, key: usize, act: Action) -> Self {
         self.other_scripts[key] = Some(act);
         self
     }
 
+    /// Sets what happens when the caller presses a key this script has no action for
+    pub fn on_err(mut self, act: Action) -> Self {
+        self.err = Box::new(act);
+        self
+    }
+
+    pub fn err_message(mut self, msg: &str) -> Self {
+        self.err_message = Some(msg.to_owned());
+        self
+    }
+
+    pub fn err_message_translated(mut self, locale: &str, msg: &str) -> Self {
+        self.err_translations.insert(locale.to_owned(), msg.to_owned());
+        self
+    }
+
+    /// After max_retries invalid inputs in a row act is run instead of the error action
+    pub fn max_retries(mut self, max_retries: u32, act: Action) -> Self {
+        self.retry_limit = Some((max_retries, Box::new(act)));
+        self
+    }
+
+    pub fn timeout(mut self, secs: u32) -> Self {
+        self.timeout = secs;
+        self
+    }
+
+    pub fn on_no_input(mut self, no_input: NoInput) -> Self {
+        self.no_input = Some(no_input);
+        self
+    }
+
+
+}
+
+
+/// Asks the caller for several digits at once (an account number, a zip code...), the digits
+/// are stored under `save_as` rather than being followed as a path, then `next` is run
+#[derive(Debug)]
+pub struct Collect {
+    /// In the script's default language
+    pub text: String,
+    /// Locale -> the text in that language
+    pub translations: HashMap<String, String>,
+    pub label: Option<String>,
+    pub save_as: String,
+    /// None means keep collecting until finish_on_key is pressed
+    pub num_digits: Option<u32>,
+    pub finish_on_key: char,
+    pub timeout: u32,
+    /// Said before asking again when something other than digits was entered
+    pub err_message: Option<String>,
+    /// Locale -> the err_message in that language
+    pub err_translations: HashMap<String, String>,
+    /// How many unusable entries in a row are allowed and what to do after that, e.g. hang up
+    pub retry_limit: Option<(u32, Box<Action>)>,
+    pub no_input: Option<NoInput>,
+    pub next: Box<Action>,
+}
+
+impl Collect {
+
+    pub fn with_text(s: &str, save_as: &str, next: Action) -> Collect {
+        Collect { text: String::from(s), translations: HashMap::new(), label: None, save_as: String::from(save_as), num_digits: None, finish_on_key: '#', timeout: DEFAULT_TIMEOUT, err_message: None, err_translations: HashMap::new(), retry_limit: None, no_input: None, next: Box::new(next) }
+    }
+
+    pub fn labeled(mut self, label: &str) -> Self {
+        self.label = Some(label.to_owned());
+        self
+    }
+
+    pub fn translated(mut self, locale: &str, text: &str) -> Self {
+        self.translations.insert(locale.to_owned(), text.to_owned());
+        self
+    }
+
+    pub fn digits(mut self, n: u32) -> Self {
+        self.num_digits = Some(n);
+        self
+    }
+
+    pub fn finish_on(mut self, key: char) -> Self {
+        self.finish_on_key = key;
+        self
+    }
+
+    pub fn err_message(mut self, msg: &str) -> Self {
+        self.err_message = Some(msg.to_owned());
+        self
+    }
 
+    pub fn err_message_translated(mut self, locale: &str, msg: &str) -> Self {
+        self.err_translations.insert(locale.to_owned(), msg.to_owned());
+        self
+    }
+
+    /// After max_retries unusable entries in a row act is run instead of asking again
+    pub fn max_retries(mut self, max_retries: u32, act: Action) -> Self {
+        self.retry_limit = Some((max_retries, Box::new(act)));
+        self
+    }
+
+    pub fn retries_exceeded(&self, attempts: u32) -> bool {
+        match self.retry_limit {
+            Some((max_retries, _)) => attempts > max_retries,
+            None => false,
+        }
+    }
+
+    pub fn timeout(mut self, secs: u32) -> Self {
+        self.timeout = secs;
+        self
+    }
+
+    pub fn on_no_input(mut self, no_input: NoInput) -> Self {
+        self.no_input = Some(no_input);
+        self
+    }
 }
 
 
+/// Seconds a prompt waits for input unless it sets its own timeout
+pub const DEFAULT_TIMEOUT: u32 = 10;
 
+/// What a prompt does when the caller doesn't press or say anything before its timeout. The prompt
+/// is said again up to max_reprompts times in a row, after that `then` is run, e.g. a hangup
+#[derive(Debug)]
+pub struct NoInput {
+    /// Said before the prompt is repeated, e.g. "Sorry, we didn't hear anything"
+    pub message: Option<String>,
+    /// Locale -> the message in that language
+    pub translations: HashMap<String, String>,
+    pub max_reprompts: u32,
+    pub then: Box<Action>,
+}
 
+impl NoInput {
+    pub fn new(max_reprompts: u32, then: Action) -> NoInput {
+        NoInput { message: None, translations: HashMap::new(), max_reprompts, then: Box::new(then) }
+    }
 
+    pub fn message(mut self, msg: &str) -> Self {
+        self.message = Some(msg.to_owned());
+        self
+    }
 
+    pub fn message_translated(mut self, locale: &str, msg: &str) -> Self {
+        self.translations.insert(locale.to_owned(), msg.to_owned());
+        self
+    }
+
+    pub fn exceeded(&self, attempts: u32) -> bool {
+        attempts > self.max_reprompts
+    }
+}
+
+
+/// Forwards the call with a <Dial>, if the number doesn't answer, is busy or the dial fails
+/// `fallback` is run instead
+#[derive(Debug)]
+pub struct Transfer {
+    /// A phone number or a sip: URI, templated like prompts so it can come from the context
+    pub to: String,
+    /// Said before dialing, e.g. "Please hold while we connect you"
+    pub text: Option<String>,
+    /// Locale -> the text in that language
+    pub translations: HashMap<String, String>,
+    /// The number the person being called sees, templated too. Twilio uses the caller's number without it
+    pub caller_id: Option<String>,
+    /// Seconds to let it ring before giving up
+    pub timeout: u32,
+    pub fallback: Box<Action>,
+}
+
+impl Transfer {
+    pub fn new(to: &str, fallback: Action) -> Transfer {
+        Transfer { to: String::from(to), text: None, translations: HashMap::new(), caller_id: None, timeout: 30, fallback: Box::new(fallback) }
+    }
+
+    pub fn with_text(mut self, s: &str) -> Self {
+        self.text = Some(String::from(s));
+        self
+    }
+
+    pub fn translated(mut self, locale: &str, text: &str) -> Self {
+        self.translations.insert(locale.to_owned(), text.to_owned());
+        self
+    }
+
+    pub fn caller_id(mut self, caller_id: &str) -> Self {
+        self.caller_id = Some(String::from(caller_id));
+        self
+    }
 
+    pub fn timeout(mut self, secs: u32) -> Self {
+        self.timeout = secs;
+        self
+    }
+}
 
 
+/// Records the caller after saying text, once they're done the recording is stored in the call's
+/// session under `name` and `next` is run
+#[derive(Debug)]
+pub struct Record {
+    pub text: String,
+    /// Locale -> the text in that language
+    pub translations: HashMap<String, String>,
+    /// Tells the call's recordings apart, e.g. "voicemail"
+    pub name: String,
+    /// Longest recording allowed in seconds
+    pub max_length: u32,
+    pub finish_on_key: char,
+    pub beep: bool,
+    /// Whether twilio should transcribe the recording too, the transcription arrives later
+    pub transcribe: bool,
+    pub next: Box<Action>,
+}
+
+impl Record {
+    pub fn with_text(s: &str, name: &str, next: Action) -> Record {
+        Record { text: String::from(s), translations: HashMap::new(), name: String::from(name), max_length: 120, finish_on_key: '#', beep: true, transcribe: false, next: Box::new(next) }
+    }
 
+    pub fn translated(mut self, locale: &str, text: &str) -> Self {
+        self.translations.insert(locale.to_owned(), text.to_owned());
+        self
+    }
+
+    pub fn max_length(mut self, secs: u32) -> Self {
+        self.max_length = secs;
+        self
+    }
+
+    pub fn finish_on(mut self, key: char) -> Self {
+        self.finish_on_key = key;
+        self
+    }
+
+    pub fn without_beep(mut self) -> Self {
+        self.beep = false;
+        self
+    }
 
+    pub fn transcribed(mut self) -> Self {
+        self.transcribe = true;
+        self
+    }
+}
 
 
+/// An SMS sent when the action is reached, e.g. "press 1 to get our address by text". If it
+/// can't be sent that's logged and the call carries on with `next` anyway
+#[derive(Debug)]
+pub struct TextMessage {
+    /// Templated like prompts, the caller's number is used without it
+    pub to: Option<String>,
+    pub text: String,
+    /// Locale -> the text in that language, picked by the call's language like prompts
+    pub translations: HashMap<String, String>,
+    pub next: Box<Action>,
+}
 
+impl TextMessage {
+    pub fn with_text(s: &str, next: Action) -> TextMessage {
+        TextMessage { to: None, text: String::from(s), translations: HashMap::new(), next: Box::new(next) }
+    }
 
+    pub fn to(mut self, number: &str) -> Self {
+        self.to = Some(String::from(number));
+        self
+    }
 
+    pub fn translated(mut self, locale: &str, text: &str) -> Self {
+        self.translations.insert(locale.to_owned(), text.to_owned());
+        self
+    }
+}
 
 
+/// Says text and ends the call
+#[derive(Debug)]
+pub struct Hangup {
+    pub text: String,
+    /// Locale -> the text in that language
+    pub translations: HashMap<String, String>,
+}
 
+impl Hangup {
+    pub fn with_text(s: &str) -> Hangup {
+        Hangup { text: String::from(s), translations: HashMap::new() }
+    }
 
+    pub fn translated(mut self, locale: &str, text: &str) -> Self {
+        self.translations.insert(locale.to_owned(), text.to_owned());
+        self
+    }
+}
 
 
+/// POSTs the call's variables and path to `url` as JSON and waits up to `timeout` seconds for an
+/// answer like `{"vars": {"balance": "12.50"}, "goto_label": "overdue"}`. Both fields are optional,
+/// the vars are stored with the call's captured variables and the call goes to the labelled node,
+/// or to `next` without a label. If the request fails, times out, or the answer isn't valid JSON
+/// or names an unknown label, `fallback` is run instead
+#[derive(Debug)]
+pub struct Webhook {
+    /// Templated like prompts
+    pub url: String,
+    pub timeout: u32,
+    /// The variables the webhook answers with, so the script can be checked for typos before it runs
+    pub provides: Vec<String>,
+    pub next: Box<Action>,
+    pub fallback: Box<Action>,
+}
 
+impl Webhook {
+    pub fn new(url: &str, next: Action, fallback: Action) -> Webhook {
+        Webhook { url: String::from(url), timeout: 5, provides: Vec::new(), next: Box::new(next), fallback: Box::new(fallback) }
+    }
 
+    pub fn timeout(mut self, secs: u32) -> Self {
+        self.timeout = secs;
+        self
+    }
 
+    pub fn provides(mut self, var: &str) -> Self {
+        self.provides.push(String::from(var));
+        self
+    }
+}
 
 
+/// Switches the rest of the call to language, prompts without a translation for it are still
+/// said in the default language
+#[derive(Debug)]
+pub struct SetLanguage {
+    /// A locale like "es-MX"
+    pub language: String,
+    pub next: Box<Action>,
+}
 
+impl SetLanguage {
+    pub fn new(language: &str, next: Action) -> SetLanguage {
+        SetLanguage { language: String::from(language), next: Box::new(next) }
+    }
+}
 
 
+/// Which languages a script is written in and which voice twilio says each of them with
+#[derive(Debug)]
+pub struct Languages {
+    /// The locale prompts' text is written in, used whenever there's no translation for the call's language
+    pub default: String,
+    /// A context variable holding the locale the call starts in, a SetLanguage overrides it
+    pub context_var: Option<String>,
+    /// Locale -> twilio voice, e.g. "es-MX" -> "Polly.Mia". Locales not listed use DEFAULT_VOICE
+    pub voices: HashMap<String, String>,
+}
 
+pub const DEFAULT_VOICE: &'static str = "woman";
 
+impl Default for Languages {
+    fn default() -> Languages {
+        Languages { default: String::from("en-US"), context_var: None, voices: HashMap::new() }
+    }
+}
 
+impl Languages {
+    pub fn voice(&self, locale: &str) -> &str {
+        self.voices.get(locale).map_or(DEFAULT_VOICE, String::as_ref)
+    }
 
+    /// A prompt's text in language and the locale it's actually in, which is the default
+    /// language when there's no translation
+    pub fn pick<'a>(&'a self, language: &'a str, text: &'a str, translations: &'a HashMap<String, String>) -> (&'a str, &'a str) {
+        match translations.get(language) {
+            Some(translation) => (translation, language),
+            None => (text, &self.default),
+        }
+    }
+}
 
 
+/// Runs open during the schedule's hours and closed outside them. On the schedule's holidays
+/// holiday is run, or closed if there isn't one
+#[derive(Debug)]
+pub struct Hours {
+    pub schedule: Schedule,
+    pub open: Box<Action>,
+    pub closed: Box<Action>,
+    pub holiday: Option<Box<Action>>,
+}
 
+impl Hours {
+    pub fn new(schedule: Schedule, open: Action, closed: Action) -> Hours {
+        Hours { schedule, open: Box::new(open), closed: Box::new(closed), holiday: None }
+    }
 
+    pub fn on_holidays(mut self, act: Action) -> Self {
+        self.holiday = Some(Box::new(act));
+        self
+    }
+}
 
 
+/// Picks one of its variants at random in proportion to their weights. The pick only depends on
+/// the call id and the split's name so a call gets the same variant every time it comes through
+#[derive(Debug)]
+pub struct Split {
+    /// Which test this is, variants are recorded and exported under it
+    pub name: String,
+    pub variants: Vec<Variant>,
+}
 
+#[derive(Debug)]
+pub struct Variant {
+    pub name: String,
+    pub weight: u32,
+    pub act: Action,
+}
 
+impl Split {
+    pub fn new(name: &str) -> Split {
+        Split { name: String::from(name), variants: Vec::new() }
+    }
 
+    pub fn variant(mut self, name: &str, weight: u32, act: Action) -> Self {
+        self.variants.push(Variant { name: String::from(name), weight, act });
+        self
+    }
 
+    /// Index of the variant call id gets, None if no variant has any weight
+    pub fn pick(&self, id: i32) -> Option<usize> {
+        let total = self.variants.iter().map(|v| v.weight as u64).sum::<u64>();
+        if total == 0 {
+            return None;
+        }
+        let mut roll = call_hash(id, &self.name) % total;
+        for (i, variant) in self.variants.iter().enumerate() {
+            if roll < variant.weight as u64 {
+                return Some(i);
+            }
+            roll -= variant.weight as u64;
+        }
+        unreachable!()
+    }
+}
 
+/// FNV-1a, spelled out rather than using std's hasher so picks don't change between Rust versions.
+/// FNV's low bits just follow the id's, so they're mixed with the high bits at the end
+fn call_hash(id: i32, name: &str) -> u64 {
+    let id_bytes = (0..4).map(|i| (id >> (8 * i)) as u8);
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.bytes().chain(id_bytes) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
 
 
+/// Runs flow, which can be shared between any number of SubFlows and ScriptBases, then next once
+/// the flow reaches a Return. Flows can run other flows
+#[derive(Debug)]
+pub struct SubFlow {
+    pub name: String,
+    pub flow: Rc<Action>,
+    pub next: Box<Action>,
+}
 
+impl SubFlow {
+    pub fn new(name: &str, flow: Rc<Action>, next: Action) -> SubFlow {
+        SubFlow { name: String::from(name), flow, next: Box::new(next) }
+    }
+}
 
 
+#[derive(Debug)]
+pub struct Branch {
+    pub condition: Condition,
+    pub then: Box<Action>,
+    pub otherwise: Box<Action>,
+}
 
+impl Branch {
+    pub fn new(condition: Condition, then: Action, otherwise: Action) -> Branch {
+        Branch { condition, then: Box::new(then), otherwise: Box::new(otherwise) }
+    }
+}
 
+/// Something that's true or false about a call's context variables
+#[derive(Debug)]
+pub enum Condition {
+    Present(String),
+    Equals(String, String),
+    /// Numeric comparisons are false when the variable isn't a number
+    LessThan(String, f64),
+    GreaterThan(String, f64),
+}
 
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Condition::Present(ref var) => write!(f, "{} is set", var),
+            &Condition::Equals(ref var, ref value) => write!(f, "{} = {:?}", var, value),
+            &Condition::LessThan(ref var, bound) => write!(f, "{} < {}", var, bound),
+            &Condition::GreaterThan(ref var, bound) => write!(f, "{} > {}", var, bound),
+        }
+    }
+}
 
+impl Condition {
+    pub fn var_name(&self) -> &str {
+        match self {
+            &Condition::Present(ref var) => var,
+            &Condition::Equals(ref var, _) => var,
+            &Condition::LessThan(ref var, _) => var,
+            &Condition::GreaterThan(ref var, _) => var,
+        }
+    }
 
+    /// resolve looks up the value of a variable, None if it isn't set
+    pub fn holds<F>(&self, resolve: F) -> bool where F: Fn(&str) -> Option<String> {
+        let value = resolve(self.var_name());
+        let number = value.as_ref().and_then(|v| v.trim().parse::<f64>().ok());
 
+        match self {
+            &Condition::Present(_) => value.is_some(),
+            &Condition::Equals(_, ref expected) => value.as_ref() == Some(expected),
+            &Condition::LessThan(_, bound) => number.map_or(false, |n| n < bound),
+            &Condition::GreaterThan(_, bound) => number.map_or(false, |n| n > bound),
+        }
+    }
+}
@@ -0,0 +1,713 @@
+//! Loads a ScriptBase from a JSON document so prompts can be changed without a recompile.
+//!
+//! ```json
+//! {
+//!     "root": {
+//!         "text": "Hello {f_name}, please press 1 or 2",
+//!         "err": "repeat",
+//!         "on": {
+//!             "1": {"script": {"text": "You pressed 1, now press 3", "on": {"3": {"hangup": "Bye"}}}},
+//!             "2": {"goto": "1"}
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Keys in "on" and "keywords" are "0"-"9", "*" or "#".
+//!
+//! An action is either one of the strings "repeat", "back", "main_menu", "repeat_prompt" and "return" or an
+//! object with exactly one of the keys "script", "collect", "record", "branch", "transfer", "text", "webhook", "set_language", "hours", "split", "sub_flow", "hangup", "goto" or "goto_label".
+//!
+//! "back" goes to the prompt above the one the caller is at, "main_menu" goes to the root and
+//! "repeat_prompt" says the current prompt again, e.g. `"on": {"*": "back", "0": "main_menu"}`.
+//!
+//! Scripts and collects can be given a "label" which "goto_label" jumps to, so jumps survive
+//! menus being reordered.
+//!
+//! A collect looks like `{"text": "Enter your zip code", "save_as": "zip", "num_digits": 5, "next": <action>}`,
+//! without "num_digits" it collects until "finish_on_key" (# by default) is pressed. Scripts can
+//! have a "save_as" too, which saves the key pressed or what was said. Saved input can be used in
+//! later prompts and conditions like any other variable, e.g. "You entered {zip}".
+//!
+//! A branch picks one of two actions depending on a context variable without waiting for the caller,
+//! `{"branch": {"if": {"greater_than": ["balance", 0]}, "then": <action>, "else": <action>}}`.
+//! Conditions are `{"present": var}`, `{"equals": [var, value]}`, `{"less_than": [var, number]}`
+//! and `{"greater_than": [var, number]}`.
+//!
+//! A script's "err_message" is said before its "err" action, and
+//! `"retry_limit": {"max": 3, "then": <action>}` runs that action instead once the caller has
+//! given the script invalid input more than 3 times in a row. A collect can have both too, it asks
+//! again after its "err_message" when something other than digits is entered.
+//!
+//! A transfer connects the caller to a phone number or sip: URI, both "to" and "caller_id" can use
+//! template variables, `{"transfer": {"to": "{agent_phone}", "text": "Please hold", "caller_id": "+15551234567",
+//! "timeout": 20, "fallback": <action>}}`. The fallback is run when nobody answers, the line is busy
+//! or the dial fails. It rings for 30 seconds without "timeout".
+//!
+//! A record saves a message from the caller, e.g. a voicemail,
+//! `{"record": {"text": "Leave a message after the beep", "name": "voicemail", "max_length": 60, "next": <action>}}`.
+//! Recordings stop at "finish_on_key" (# by default) or after "max_length" seconds (120 by default),
+//! `"beep": false` turns the beep off and `"transcribe": true` asks twilio for a transcription.
+//! Recordings are listed by GET /recordings.
+//!
+//! A text sends an SMS and goes straight on to "next", to the caller's number unless "to" is given,
+//! `{"text": {"text": "Our address is 1 Main St", "to": "{phone}", "next": <action>}}`. If the SMS can't
+//! be sent the call carries on anyway.
+//!
+//! A webhook asks another system what to do without the caller hearing anything,
+//! `{"webhook": {"url": "https://example.com/orders?phone={phone}", "timeout": 3, "provides": ["order_status"],
+//! "next": <action>, "fallback": <action>}}`. It's sent the call's variables and can answer with more
+//! of them and/or a label to go to instead of "next", see webhook.rs. "provides" lists the variables
+//! it answers with so prompts can use them. The fallback is run if there's no answer within
+//! "timeout" seconds (5 by default) or the answer is no good.
+//!
+//! An hours action goes to "open" or "closed" depending on the time in "time_zone", without the caller
+//! hearing anything, `{"hours": {"time_zone": "America/New_York", "weekly": {"mon-fri": "09:00-17:00",
+//! "sat": "10:00-12:00, 13:00-15:00"}, "holidays": "holidays.txt", "open": <action>, "closed": <action>,
+//! "holiday": <action>}}`. On a date in the "holidays" file it goes to "holiday", or "closed" if there
+//! isn't one, see hours.rs for the file's format.
+//!
+//! A split sends each call down one of its variants at random, weighted, to compare them,
+//! `{"split": {"name": "greeting_test", "variants": [{"name": "short", "weight": 1, "then": <action>},
+//! {"name": "long", "weight": 3, "then": <action>}]}}`. A call always gets the same variant. Which one
+//! it got shows up in GET /results, and GET /results/variants compares how many calls of each
+//! variant made it to the end of the script.
+//!
+//! Steps used in several places, e.g. checking the caller's date of birth, can be written once as a
+//! sub-flow under "flows", `"flows": {"verify_dob": {"collect": {"text": "Enter your date of birth",
+//! "save_as": "dob", "next": "return"}}}`, and run from anywhere with
+//! `{"sub_flow": {"name": "verify_dob", "next": <action>}}`. "return" ends the flow and goes on to the
+//! "next" of the sub_flow that ran it. Flows shared between scripts can go in their own files, a JSON
+//! object of names to flows, listed in `"flow_files": ["scripts/flows/common.json"]`. Labels can't be
+//! used inside a flow and "goto" paths are always from the script's root.
+//!
+//! Prompts are written in the script's default language (en-US unless the file has e.g.
+//! `"languages": {"default": "en-US", "context_var": "language", "voices": {"es-MX": "Polly.Mia"}}`).
+//! Scripts and collects can have `"translations": {"es-MX": "Hola, presione 1 o 2"}`, which are said
+//! to calls in that language. So can records, transfers and texts, and a hangup can be
+//! `{"hangup": {"text": "Bye", "translations": {"es-MX": "Adiós"}}}`. An "err_message" is translated
+//! with "err_message_translations" and a no_input "message" with "message_translations". A call's
+//! language comes from "context_var" if the Context has it, or from a
+//! `{"set_language": {"language": "es-MX", "next": <action>}}` reached on the way, e.g. under
+//! "press 2 for Spanish". Anything without a translation is said in the default language. Each
+//! language is said with its voice from "voices", or "woman".
+//!
+//! Any text that's said can play recorded audio too, e.g. `"[play:intro.mp3|Welcome] {f_name}"`, see prompt.rs,
+//! and use SSML tags like `<break time=\"1s\"/>` and `<say-as interpret-as=\"telephone\">`, see ssml.rs.
+//!
+//! Scripts and collects wait 10 seconds for input unless they set `"timeout"`. If the caller says
+//! nothing the call ends, unless the prompt has e.g.
+//! `"no_input": {"message": "Sorry, we didn't hear anything.", "max_reprompts": 2, "then": {"hangup": "Goodbye"}}`,
+//! then the message and the prompt are said again up to 2 times in a row before running "then".
+//!
+//! Scripts can also listen for speech with `"input": "speech"` (or `"dtmf speech"`), then
+//! `"keywords": {"1": ["billing", "pay my bill"]}` makes saying those the same as pressing 1,
+//! results under `"min_confidence"` (0.5 by default) are treated as invalid input. Speech is recognized
+//! in the default language unless the keywords are translated to the call's language with
+//! `"keyword_translations": {"es-MX": {"1": ["facturación"]}}`.
+
+extern crate chrono;
+extern crate chrono_tz;
+extern crate serde_json;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::rc::Rc;
+use serde::de::{self, Deserialize, Deserializer};
+
+use self::chrono::{NaiveTime, Weekday};
+use self::chrono_tz::Tz;
+
+use hours::{self, Schedule};
+use script::{self, ScriptBase, Script, Collect, Branch, Condition, Action, Input, NoInput, Transfer, Record, TextMessage, Webhook, Hangup,
+             SetLanguage, Languages, Hours, Split, SubFlow};
+
+
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(String, ::std::io::Error),
+    Parse(serde_json::error::Error),
+    /// A sub-flow that's missing, defined twice, runs itself or is in a flow file that doesn't parse
+    Flow(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &LoadError::Io(ref path, ref e) => write!(f, "Couldn't read script file {:?}: {}", path, e),
+            // serde_json already appends "at line X column Y" to its messages
+            &LoadError::Parse(ref e) => write!(f, "Invalid script file: {}", e),
+            &LoadError::Flow(ref msg) => write!(f, "Invalid sub-flows: {}", msg),
+        }
+    }
+}
+
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptFile {
+    root: ScriptSpec,
+    languages: Option<LanguagesSpec>,
+    #[serde(default)]
+    flows: HashMap<String, ActionSpec>,
+    #[serde(default)]
+    flow_files: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LanguagesSpec {
+    default: Option<String>,
+    context_var: Option<String>,
+    #[serde(default)]
+    voices: HashMap<String, String>,
+}
+
+impl LanguagesSpec {
+    fn into_languages(self) -> Languages {
+        let mut languages = Languages::default();
+        if let Some(default) = self.default {
+            languages.default = default;
+        }
+        languages.context_var = self.context_var;
+        languages.voices = self.voices;
+        languages
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScriptSpec {
+    text: String,
+    #[serde(default)]
+    translations: HashMap<String, String>,
+    label: Option<String>,
+    save_as: Option<String>,
+    #[serde(default)]
+    on: HashMap<Key, ActionSpec>,
+    err: Option<Box<ActionSpec>>,
+    err_message: Option<String>,
+    #[serde(default)]
+    err_message_translations: HashMap<String, String>,
+    retry_limit: Option<RetryLimitSpec>,
+    input: Option<InputSpec>,
+    #[serde(default)]
+    keywords: HashMap<Key, Vec<String>>,
+    #[serde(default)]
+    keyword_translations: HashMap<String, HashMap<Key, Vec<String>>>,
+    min_confidence: Option<f32>,
+    timeout: Option<u32>,
+    no_input: Option<NoInputSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RetryLimitSpec {
+    max: u32,
+    then: Box<ActionSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoInputSpec {
+    message: Option<String>,
+    #[serde(default)]
+    message_translations: HashMap<String, String>,
+    max_reprompts: u32,
+    then: Box<ActionSpec>,
+}
+
+impl NoInputSpec {
+    fn into_no_input(self, flows: &Flows) -> NoInput {
+        let mut no_input = NoInput::new(self.max_reprompts, self.then.into_action(flows));
+        no_input.message = self.message;
+        no_input.translations = self.message_translations;
+        no_input
+    }
+}
+
+#[derive(Deserialize)]
+enum InputSpec {
+    #[serde(rename = "dtmf")]
+    Dtmf,
+    #[serde(rename = "speech")]
+    Speech,
+    #[serde(rename = "dtmf speech")]
+    DtmfSpeech,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CollectSpec {
+    text: String,
+    #[serde(default)]
+    translations: HashMap<String, String>,
+    label: Option<String>,
+    save_as: String,
+    num_digits: Option<u32>,
+    finish_on_key: Option<char>,
+    timeout: Option<u32>,
+    err_message: Option<String>,
+    #[serde(default)]
+    err_message_translations: HashMap<String, String>,
+    retry_limit: Option<RetryLimitSpec>,
+    no_input: Option<NoInputSpec>,
+    next: Box<ActionSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ActionSpec {
+    Script(ScriptSpec),
+    Collect(CollectSpec),
+    Hangup(HangupSpec),
+    Goto(String),
+    GotoLabel(String),
+    Branch(BranchSpec),
+    Repeat,
+    Back,
+    MainMenu,
+    RepeatPrompt,
+    Transfer(TransferSpec),
+    Record(RecordSpec),
+    Text(TextSpec),
+    Webhook(WebhookSpec),
+    SetLanguage(SetLanguageSpec),
+    Hours(HoursSpec),
+    Split(SplitSpec),
+    SubFlow(SubFlowSpec),
+    Return,
+}
+
+/// Either just the text or `{"text": ..., "translations": {...}}`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HangupSpec {
+    Text(String),
+    Translated(TranslatedHangupSpec),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TranslatedHangupSpec {
+    text: String,
+    #[serde(default)]
+    translations: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubFlowSpec {
+    name: String,
+    next: Box<ActionSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SplitSpec {
+    name: String,
+    variants: Vec<VariantSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VariantSpec {
+    name: String,
+    weight: u32,
+    then: Box<ActionSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HoursSpec {
+    time_zone: TimeZone,
+    #[serde(default)]
+    weekly: HashMap<Days, OpeningHours>,
+    holidays: Option<String>,
+    open: Box<ActionSpec>,
+    closed: Box<ActionSpec>,
+    holiday: Option<Box<ActionSpec>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SetLanguageSpec {
+    language: String,
+    next: Box<ActionSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WebhookSpec {
+    url: String,
+    timeout: Option<u32>,
+    #[serde(default)]
+    provides: Vec<String>,
+    next: Box<ActionSpec>,
+    fallback: Box<ActionSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextSpec {
+    to: Option<String>,
+    text: String,
+    #[serde(default)]
+    translations: HashMap<String, String>,
+    next: Box<ActionSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RecordSpec {
+    text: String,
+    #[serde(default)]
+    translations: HashMap<String, String>,
+    name: String,
+    max_length: Option<u32>,
+    finish_on_key: Option<char>,
+    beep: Option<bool>,
+    #[serde(default)]
+    transcribe: bool,
+    next: Box<ActionSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransferSpec {
+    to: String,
+    text: Option<String>,
+    #[serde(default)]
+    translations: HashMap<String, String>,
+    caller_id: Option<String>,
+    timeout: Option<u32>,
+    fallback: Box<ActionSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BranchSpec {
+    #[serde(rename = "if")]
+    condition: ConditionSpec,
+    then: Box<ActionSpec>,
+    #[serde(rename = "else")]
+    otherwise: Box<ActionSpec>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ConditionSpec {
+    Present(String),
+    Equals(String, String),
+    LessThan(String, f64),
+    GreaterThan(String, f64),
+}
+
+/// A key on the phone's keypad, checked while parsing so bad keys are reported with a line number
+#[derive(PartialEq, Eq, Hash)]
+struct Key(usize);
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D>(deserializer: D) -> Result<Key, D::Error> where D: Deserializer<'de> {
+        let s = String::deserialize(deserializer)?;
+        let mut chars = s.chars();
+        match (chars.next().and_then(script::key_index), chars.next()) {
+            (Some(key), None) => Ok(Key(key)),
+            _ => Err(de::Error::custom(format!("invalid key {:?}, expected 0-9, * or #", s))),
+        }
+    }
+}
+
+
+/// A time zone like "America/New_York"
+struct TimeZone(Tz);
+
+impl<'de> Deserialize<'de> for TimeZone {
+    fn deserialize<D>(deserializer: D) -> Result<TimeZone, D::Error> where D: Deserializer<'de> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map(TimeZone).map_err(|_| de::Error::custom(format!("unknown time zone {:?}, expected one like America/New_York", s)))
+    }
+}
+
+/// A day like "mon" or a range like "mon-fri"
+#[derive(PartialEq, Eq, Hash)]
+struct Days(Vec<Weekday>);
+
+impl<'de> Deserialize<'de> for Days {
+    fn deserialize<D>(deserializer: D) -> Result<Days, D::Error> where D: Deserializer<'de> {
+        let s = String::deserialize(deserializer)?;
+        hours::parse_days(&s).map(Days).map_err(de::Error::custom)
+    }
+}
+
+/// Like "09:00-17:00" or "09:00-12:00, 13:00-17:00"
+struct OpeningHours(Vec<(NaiveTime, NaiveTime)>);
+
+impl<'de> Deserialize<'de> for OpeningHours {
+    fn deserialize<D>(deserializer: D) -> Result<OpeningHours, D::Error> where D: Deserializer<'de> {
+        let s = String::deserialize(deserializer)?;
+        hours::parse_hours(&s).map(OpeningHours).map_err(de::Error::custom)
+    }
+}
+
+
+impl ScriptSpec {
+    fn into_script(self, flows: &Flows) -> Script {
+        let mut script = Script::with_text(&self.text);
+        script.translations = self.translations;
+        if let Some(label) = self.label {
+            script = script.labeled(&label);
+        }
+        if let Some(name) = self.save_as {
+            script = script.save_input_as(&name);
+        }
+        for (Key(key), act_spec) in self.on {
+            script = script.on(key, act_spec.into_action(flows));
+        }
+        if let Some(err_spec) = self.err {
+            script = script.on_err(err_spec.into_action(flows));
+        }
+        if let Some(msg) = self.err_message {
+            script = script.err_message(&msg);
+        }
+        script.err_translations = self.err_message_translations;
+        if let Some(limit) = self.retry_limit {
+            script = script.max_retries(limit.max, limit.then.into_action(flows));
+        }
+        if let Some(input_spec) = self.input {
+            script = script.listen_for(match input_spec {
+                InputSpec::Dtmf => Input::Dtmf,
+                InputSpec::Speech => Input::Speech,
+                InputSpec::DtmfSpeech => Input::DtmfSpeech,
+            });
+        }
+        for (Key(key), words) in self.keywords {
+            script = script.on_words(key, &words.iter().map(String::as_ref).collect::<Vec<&str>>());
+        }
+        for (locale, keywords) in self.keyword_translations {
+            for (Key(key), words) in keywords {
+                script = script.on_words_in(&locale, key, &words.iter().map(String::as_ref).collect::<Vec<&str>>());
+            }
+        }
+        if let Some(confidence) = self.min_confidence {
+            script = script.min_confidence(confidence);
+        }
+        if let Some(secs) = self.timeout {
+            script = script.timeout(secs);
+        }
+        if let Some(no_input_spec) = self.no_input {
+            script = script.on_no_input(no_input_spec.into_no_input(flows));
+        }
+        script
+    }
+}
+
+impl ActionSpec {
+    fn into_action(self, flows: &Flows) -> Action {
+        match self {
+            ActionSpec::Script(spec) => Action::ExecuteScript(spec.into_script(flows)),
+            ActionSpec::Collect(spec) => {
+                let mut collect = Collect::with_text(&spec.text, &spec.save_as, spec.next.into_action(flows));
+                collect.translations = spec.translations;
+                collect.num_digits = spec.num_digits;
+                collect.label = spec.label;
+                if let Some(key) = spec.finish_on_key {
+                    collect = collect.finish_on(key);
+                }
+                if let Some(secs) = spec.timeout {
+                    collect = collect.timeout(secs);
+                }
+                if let Some(msg) = spec.err_message {
+                    collect = collect.err_message(&msg);
+                }
+                collect.err_translations = spec.err_message_translations;
+                if let Some(limit) = spec.retry_limit {
+                    collect = collect.max_retries(limit.max, limit.then.into_action(flows));
+                }
+                if let Some(no_input_spec) = spec.no_input {
+                    collect = collect.on_no_input(no_input_spec.into_no_input(flows));
+                }
+                Action::Collect(collect)
+            }
+            ActionSpec::Hangup(HangupSpec::Text(text)) => Action::HangupWithMessage(Hangup::with_text(&text)),
+            ActionSpec::Hangup(HangupSpec::Translated(spec)) => {
+                let mut hangup = Hangup::with_text(&spec.text);
+                hangup.translations = spec.translations;
+                Action::HangupWithMessage(hangup)
+            }
+            ActionSpec::Goto(path) => Action::GoToAction(path),
+            ActionSpec::GotoLabel(label) => Action::GoToLabel(label),
+            ActionSpec::Branch(spec) => {
+                let condition = match spec.condition {
+                    ConditionSpec::Present(var) => Condition::Present(var),
+                    ConditionSpec::Equals(var, value) => Condition::Equals(var, value),
+                    ConditionSpec::LessThan(var, bound) => Condition::LessThan(var, bound),
+                    ConditionSpec::GreaterThan(var, bound) => Condition::GreaterThan(var, bound),
+                };
+                Action::Branch(Branch::new(condition, spec.then.into_action(flows), spec.otherwise.into_action(flows)))
+            }
+            ActionSpec::Repeat => Action::Repeat,
+            ActionSpec::Back => Action::Back,
+            ActionSpec::MainMenu => Action::MainMenu,
+            ActionSpec::RepeatPrompt => Action::RepeatPrompt,
+            ActionSpec::Transfer(spec) => {
+                let mut transfer = Transfer::new(&spec.to, spec.fallback.into_action(flows));
+                transfer.text = spec.text;
+                transfer.translations = spec.translations;
+                transfer.caller_id = spec.caller_id;
+                if let Some(secs) = spec.timeout {
+                    transfer = transfer.timeout(secs);
+                }
+                Action::Transfer(transfer)
+            }
+            ActionSpec::Record(spec) => {
+                let mut record = Record::with_text(&spec.text, &spec.name, spec.next.into_action(flows));
+                record.translations = spec.translations;
+                if let Some(secs) = spec.max_length {
+                    record = record.max_length(secs);
+                }
+                if let Some(key) = spec.finish_on_key {
+                    record = record.finish_on(key);
+                }
+                record.beep = spec.beep.unwrap_or(true);
+                record.transcribe = spec.transcribe;
+                Action::Record(record)
+            }
+            ActionSpec::Text(spec) => {
+                let mut text = TextMessage::with_text(&spec.text, spec.next.into_action(flows));
+                text.to = spec.to;
+                text.translations = spec.translations;
+                Action::SendText(text)
+            }
+            ActionSpec::Webhook(spec) => {
+                let mut hook = Webhook::new(&spec.url, spec.next.into_action(flows), spec.fallback.into_action(flows));
+                if let Some(secs) = spec.timeout {
+                    hook = hook.timeout(secs);
+                }
+                hook.provides = spec.provides;
+                Action::Webhook(hook)
+            }
+            ActionSpec::SetLanguage(spec) => Action::SetLanguage(SetLanguage::new(&spec.language, spec.next.into_action(flows))),
+            ActionSpec::Hours(spec) => {
+                let mut schedule = Schedule::new(spec.time_zone.0);
+                for (Days(days), OpeningHours(ranges)) in spec.weekly {
+                    for &day in days.iter() {
+                        for &(from, until) in ranges.iter() {
+                            schedule = schedule.open(day, from, until);
+                        }
+                    }
+                }
+                if let Some(path) = spec.holidays {
+                    schedule = schedule.holidays(&path);
+                }
+                let mut hours = Hours::new(schedule, spec.open.into_action(flows), spec.closed.into_action(flows));
+                if let Some(holiday) = spec.holiday {
+                    hours = hours.on_holidays(holiday.into_action(flows));
+                }
+                Action::Hours(hours)
+            }
+            ActionSpec::Split(spec) => {
+                let mut split = Split::new(&spec.name);
+                for variant in spec.variants {
+                    split = split.variant(&variant.name, variant.weight, variant.then.into_action(flows));
+                }
+                Action::Split(split)
+            }
+            ActionSpec::SubFlow(spec) => Action::SubFlow(SubFlow::new(&spec.name, flows.get(&spec.name), spec.next.into_action(flows))),
+            ActionSpec::Return => Action::Return,
+        }
+    }
+}
+
+
+/// The sub-flows a script can run. Each one is built the first time it's run and shared by every
+/// SubFlow after that
+struct Flows {
+    specs: RefCell<HashMap<String, ActionSpec>>,
+    built: RefCell<HashMap<String, Rc<Action>>>,
+    /// The flows being built right now, innermost last, so a flow that runs itself is caught
+    building: RefCell<Vec<String>>,
+    errors: RefCell<Vec<String>>,
+}
+
+impl Flows {
+    fn new(specs: HashMap<String, ActionSpec>) -> Flows {
+        Flows { specs: RefCell::new(specs), built: RefCell::new(HashMap::new()), building: RefCell::new(Vec::new()), errors: RefCell::new(Vec::new()) }
+    }
+
+    fn get(&self, name: &str) -> Rc<Action> {
+        if let Some(flow) = self.built.borrow().get(name) {
+            return Rc::clone(flow);
+        }
+
+        let spec = self.specs.borrow_mut().remove(name);
+        match spec {
+            Some(spec) => {
+                self.building.borrow_mut().push(name.to_owned());
+                let flow = Rc::new(spec.into_action(self));
+                self.building.borrow_mut().pop();
+                self.built.borrow_mut().insert(name.to_owned(), Rc::clone(&flow));
+                flow
+            }
+            None => {
+                let building = self.building.borrow();
+                let msg = if building.iter().any(|b| b == name) {
+                    let chain = building.iter().map(String::as_ref).chain(Some(name)).map(|f| format!("{:?}", f)).collect::<Vec<String>>();
+                    format!("sub-flow {:?} ends up running itself: {}", name, chain.join(" -> "))
+                }
+                else {
+                    format!("there's no sub-flow called {:?}", name)
+                };
+                self.errors.borrow_mut().push(msg);
+                // Never run, the script fails to load
+                Rc::new(Action::Return)
+            }
+        }
+    }
+}
+
+/// Reads a file of flows shared between scripts, a JSON object of names to flows
+fn load_flow_file(path: &str) -> Result<HashMap<String, ActionSpec>, LoadError> {
+    let mut src = String::new();
+    ::std::fs::File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut src))
+        .map_err(|e| LoadError::Io(path.to_owned(), e))?;
+    serde_json::from_str(&src).map_err(|e| LoadError::Flow(format!("invalid flow file {:?}: {}", path, e)))
+}
+
+
+pub fn load_str(src: &str) -> Result<ScriptBase, LoadError> {
+    let file: ScriptFile = serde_json::from_str(src).map_err(LoadError::Parse)?;
+    let languages = file.languages.map_or(Languages::default(), LanguagesSpec::into_languages);
+
+    let mut flow_specs = file.flows;
+    for path in file.flow_files.iter() {
+        for (name, spec) in load_flow_file(path)? {
+            if flow_specs.contains_key(&name) {
+                return Err(LoadError::Flow(format!("sub-flow {:?} is defined more than once, the second time in {:?}", name, path)));
+            }
+            flow_specs.insert(name, spec);
+        }
+    }
+    let flows = Flows::new(flow_specs);
+
+    let root = file.root.into_script(&flows);
+    let mut errors = flows.errors.into_inner();
+    errors.sort();
+    errors.dedup();
+    if !errors.is_empty() {
+        return Err(LoadError::Flow(errors.join(", ")));
+    }
+    Ok(ScriptBase::from_root(root).with_languages(languages))
+}
+
+pub fn load_file(path: &str) -> Result<ScriptBase, LoadError> {
+    let mut src = String::new();
+    ::std::fs::File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut src))
+        .map_err(|e| LoadError::Io(path.to_owned(), e))?;
+    load_str(&src)
+}
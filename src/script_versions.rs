@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use script::ScriptBase;
+use script_loader;
+use validate;
+
+
+/// Every version of the script that has been served. Calls stay on the version they started with,
+/// so reloading the script never changes the tree under a caller's feet. Old versions are kept
+/// around for as long as the server runs.
+pub struct ScriptVersions {
+    versions: Vec<Rc<ScriptBase>>,
+    /// The file the script is reloaded from, None if it was built in Rust
+    source: Option<String>,
+    /// Context variables new versions are validated against
+    ctx_vars: Vec<String>,
+}
+
+impl ScriptVersions {
+    pub fn new(first: ScriptBase, source: Option<String>, ctx_vars: &[&str]) -> ScriptVersions {
+        ScriptVersions {
+            versions: vec![Rc::new(first)],
+            source,
+            ctx_vars: ctx_vars.iter().map(|v| v.to_string()).collect(),
+        }
+    }
+
+    /// Versions are numbered from 1
+    pub fn latest_version(&self) -> usize {
+        self.versions.len()
+    }
+
+    pub fn get(&self, version: usize) -> Option<Rc<ScriptBase>> {
+        version.checked_sub(1).and_then(|i| self.versions.get(i)).map(Rc::clone)
+    }
+
+    /// Loads the script file again and makes it the version new calls get, the new version
+    /// is only used if it passes validation. Returns the new version number
+    pub fn reload(&mut self) -> Result<usize, String> {
+        let path = match self.source {
+            Some(ref path) => path.clone(),
+            None => return Err("The script wasn't loaded from a file, there's nothing to reload".to_owned()),
+        };
+
+        let script_base = script_loader::load_file(&path).map_err(|e| e.to_string())?;
+
+        let ctx_vars = self.ctx_vars.iter().map(String::as_ref).collect::<Vec<&str>>();
+        let problems = validate::validate(&script_base, &ctx_vars);
+        if problems.iter().any(|p| p.severity == validate::Severity::Error) {
+            return Err(problems.iter().map(|p| p.to_string()).collect::<Vec<String>>().join("\n"));
+        }
+
+        self.versions.push(Rc::new(script_base));
+        Ok(self.latest_version())
+    }
+}
+
+
+/// Every script the server runs, by name. Each call runs one of them, the name is in every
+/// callback url twilio sends the call's requests to
+pub struct ScriptRegistry {
+    scripts: HashMap<String, ScriptVersions>,
+}
+
+impl ScriptRegistry {
+    pub fn new() -> ScriptRegistry {
+        ScriptRegistry { scripts: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, name: &str, versions: ScriptVersions) {
+        self.scripts.insert(name.to_owned(), versions);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ScriptVersions> {
+        self.scripts.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut ScriptVersions> {
+        self.scripts.get_mut(name)
+    }
+
+    /// The name of the script a request asked for. Requests only have to name one when there's
+    /// more than one to choose from
+    pub fn resolve(&self, name: Option<&str>) -> Result<String, String> {
+        match name {
+            Some(name) if self.scripts.contains_key(name) => Ok(name.to_owned()),
+            Some(name) => Err(format!("Unknown script {:?}, the scripts are {}", name, self.names().join(", "))),
+            None if self.scripts.len() == 1 => Ok(self.scripts.keys().next().unwrap().clone()),
+            None => Err(format!("Missing script, the scripts are {}", self.names().join(", "))),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.scripts.keys().map(String::as_ref).collect::<Vec<&str>>();
+        names.sort();
+        names
+    }
+}
@@ -0,0 +1,174 @@
+//! Walks a script in the terminal without twilio, ngrok or a phone. Type the keys you'd press and
+//! it prints what the caller would hear along with the TwiML the server would send back.
+//!
+//! - keys are sent as `Digits`, e.g. `1` or `*` for a menu or `12345#` for a collect
+//! - `say <words>` is sent as a `SpeechResult`
+//! - `record <seconds>` finishes a recording that long
+//! - `dial <status>` is sent as the `DialCallStatus` of a transfer, e.g. `dial no-answer` or `dial completed`
+//! - `time <time>` sets the time Hours actions see from then on, e.g. `time 2026-12-25T10:00:00-05:00`.
+//!   It doesn't send anything, until it's used the real time is used
+//! - an empty line is no input at all, as if the prompt timed out
+//! - `q` quits
+//!
+//! Nothing is sent anywhere. When the script calls a webhook the request is printed and you type
+//! the JSON it answers with, an empty line is a webhook that failed or never answered. With
+//! `--live-webhooks` they're really sent instead, so a script can be tried against a local
+//! stand-in for the service it asks.
+
+extern crate serde_json;
+extern crate tokio_core;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use call_flow;
+use ctxmgr::{Context, ContextManager};
+use hours::{self, Clock, FixedClock, SystemClock};
+use script::ScriptBase;
+use twil_api::Twilio;
+use webhook;
+
+
+const CALLBACK_URL: &'static str = "http://simulator/";
+/// Twilio sends the number it called with every request, texts to the caller go here
+const CALLER_NUMBER: &'static str = "+15555550100";
+
+
+/// Reads the sample context to simulate with, a JSON object of variable names to values
+pub fn load_context_kvs(path: &str) -> Result<HashMap<String, String>, String> {
+    let file = ::std::fs::File::open(path).map_err(|e| format!("Couldn't read context file {:?}: {}", path, e))?;
+    serde_json::from_reader(file).map_err(|e| format!("Invalid context file: {}", e))
+}
+
+/// Walks sb as the script called script_name, the name only shows up in the TwiML's urls.
+/// live_webhooks sends the script's webhooks rather than asking for their answers
+pub fn run<T>(sb: &ScriptBase, script_name: &str, ctx: T, live_webhooks: bool) where T: Context + ::std::fmt::Debug {
+    let ctx_mgr = RefCell::new(ContextManager::new());
+    let id = ctx_mgr.borrow_mut().insert_context(ctx);
+    let mut live = if live_webhooks {
+        let core = tokio_core::reactor::Core::new().expect("Couldn't start the event loop");
+        let twilio = Twilio::new(&core.handle());
+        Some((core, twilio))
+    } else {
+        None
+    };
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut path = String::new();
+    let mut body_params = call_params();
+    let mut no_input = false;
+    let mut handles_no_input = false;
+    let mut clock: Box<Clock> = Box::new(SystemClock);
+
+    loop {
+        let mut outcome = call_flow::respond(sb, &ctx_mgr, id, &path, no_input, &body_params, CALLBACK_URL, script_name, &*clock);
+        let result = loop {
+            match outcome {
+                Ok(call_flow::Outcome::Webhook(call)) => {
+                    println!("\nPOST {} {}", call.url, call.body);
+                    let answer = match live {
+                        Some((ref mut core, ref twilio)) => {
+                            let handle = core.handle();
+                            core.run(webhook::post(twilio, &handle, &call.url, call.body.clone(), call.timeout))
+                        }
+                        None => typed_answer(&mut lines),
+                    };
+                    println!("Webhook answered {:?}", answer);
+                    outcome = call_flow::webhook_done(sb, &ctx_mgr, id, call, answer, CALLBACK_URL, script_name, &*clock);
+                }
+                Ok(call_flow::Outcome::Reply(reply)) => break Ok(reply),
+                Err(e) => break Err(e),
+            }
+        };
+        match result {
+            Ok(reply) => {
+                handles_no_input = reply.handles_no_input;
+                for text in reply.texts.iter() {
+                    println!("\nTexted {}: {}", text.to, text.body);
+                }
+                println!("\nCaller hears: {}", reply.said);
+                println!("{}", reply.twiml.as_str().trim());
+                match reply.next_path {
+                    Some(next_path) => path = next_path,
+                    None => {
+                        println!("Call ended");
+                        return;
+                    }
+                }
+            }
+            // The server would answer twilio with a 400 and the caller stays where they were
+            Err(e) => println!("\nBad request: {}", e),
+        }
+
+        let line = loop {
+            print!("[path {:?}] > ", path);
+            io::stdout().flush().unwrap();
+
+            let line = match lines.next() {
+                Some(Ok(line)) => line.trim().to_owned(),
+                _ => return,
+            };
+            if !line.starts_with("time ") {
+                break line;
+            }
+            match hours::parse_time(&line["time ".len()..]) {
+                Ok(time) => {
+                    println!("It's now {}", time);
+                    clock = Box::new(FixedClock(time));
+                }
+                Err(e) => println!("{}", e),
+            }
+        };
+
+        body_params = call_params();
+        no_input = false;
+        if line == "q" {
+            return;
+        }
+        else if line.is_empty() {
+            if !handles_no_input {
+                println!("No input, twilio runs out of TwiML and the call ends");
+                return;
+            }
+            no_input = true;
+        }
+        else if line.starts_with("record ") {
+            body_params.insert("RecordingUrl".to_owned(), format!("{}recordings/{}", CALLBACK_URL, path));
+            body_params.insert("RecordingDuration".to_owned(), line["record ".len()..].trim().to_owned());
+        }
+        else if line.starts_with("dial ") {
+            body_params.insert("DialCallStatus".to_owned(), line["dial ".len()..].trim().to_owned());
+        }
+        else if line.starts_with("say ") {
+            body_params.insert("SpeechResult".to_owned(), line["say ".len()..].trim().to_owned());
+            body_params.insert("Confidence".to_owned(), "1.0".to_owned());
+        }
+        else if line.len() > 1 {
+            // A collect's finishOnKey isn't sent to us by twilio, a single # is a menu key though
+            body_params.insert("Digits".to_owned(), line.trim_right_matches('#').to_owned());
+        }
+        else {
+            body_params.insert("Digits".to_owned(), line);
+        }
+    }
+}
+
+/// Asks for what the webhook answers with instead of sending it
+fn typed_answer<B: BufRead>(lines: &mut io::Lines<B>) -> Result<String, String> {
+    print!("[webhook answer] > ");
+    io::stdout().flush().unwrap();
+    match lines.next() {
+        Some(Ok(ref line)) if !line.trim().is_empty() => Ok(line.trim().to_owned()),
+        _ => Err("No answer".to_owned()),
+    }
+}
+
+/// The params twilio sends with every request of a call we started
+fn call_params() -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    params.insert("Direction".to_owned(), "outbound-api".to_owned());
+    params.insert("To".to_owned(), CALLER_NUMBER.to_owned());
+    params
+}
@@ -0,0 +1,153 @@
+//! Prompts can use a few SSML tags to control how they're said, e.g.
+//! `"Your code is <say-as interpret-as=\"characters\">{code}</say-as>. <break time=\"1s\"/>Goodbye"`.
+//! The tags are `<break/>` (time, strength), `<say-as>` (interpret-as, format), `<prosody>`
+//! (rate, pitch, volume) and `<emphasis>` (level). Everything outside the tags is plain text, so
+//! "Barnes & Noble" is fine as is. Twilio only understands SSML with Polly and Google voices.
+
+extern crate regex;
+
+
+/// Each tag we allow and the attributes it can have
+const TAGS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("break", &["time", "strength"]),
+    ("say-as", &["interpret-as", "format"]),
+    ("prosody", &["rate", "pitch", "volume"]),
+    ("emphasis", &["level"]),
+];
+
+/// What `<say-as interpret-as="...">` can be
+const INTERPRET_AS: &'static [&'static str] = &[
+    "characters", "spell-out", "cardinal", "number", "ordinal", "digits", "fraction", "unit",
+    "date", "time", "address", "expletive", "telephone",
+];
+
+
+thread_local! {
+    /// Compiled once per thread, every <Say> goes through these
+    static TAG_RE: regex::Regex = regex::Regex::new(r#"<(/?)([A-Za-z-]*)((?:\s+[A-Za-z-]+\s*=\s*"[^"<>&]*")*)\s*(/?)>"#).unwrap();
+    static ATTR_RE: regex::Regex = regex::Regex::new(r#"([A-Za-z-]+)\s*=\s*"([^"]*)""#).unwrap();
+}
+
+pub fn has_tags(text: &str) -> bool {
+    TAG_RE.with(|tag_re| tag_re.is_match(text))
+}
+
+/// Checks text only uses the tags above, with their attributes, and that every tag is closed
+pub fn check(text: &str) -> Result<(), String> {
+    TAG_RE.with(|tag_re| ATTR_RE.with(|attr_re| check_with(text, tag_re, attr_re)))
+}
+
+fn check_with(text: &str, tag_re: &regex::Regex, attr_re: &regex::Regex) -> Result<(), String> {
+    let mut open = Vec::new();
+    let mut last_end = 0;
+    for cap in tag_re.captures_iter(text) {
+        let whole = cap.get(0).unwrap();
+        check_plain(&text[last_end..whole.start()])?;
+        last_end = whole.end();
+
+        let group = |i| cap.get(i).unwrap().as_str();
+        let (closing, name, attrs, self_closing) = (group(1) == "/", group(2), group(3), group(4) == "/");
+        let allowed_attrs = match TAGS.iter().find(|&&(tag, _)| tag == name) {
+            Some(&(_, allowed_attrs)) => allowed_attrs,
+            None => return Err(format!("<{}> isn't a supported SSML tag", name)),
+        };
+
+        if closing {
+            if !attrs.is_empty() || self_closing {
+                return Err(format!("{:?} isn't a valid closing tag", whole.as_str()));
+            }
+            match open.pop() {
+                Some(open_name) if open_name == name => {}
+                Some(open_name) => return Err(format!("</{}> closes <{}>", name, open_name)),
+                None => return Err(format!("</{}> doesn't close anything", name)),
+            }
+            continue;
+        }
+
+        for attr in attr_re.captures_iter(attrs) {
+            if !allowed_attrs.contains(&&attr[1]) {
+                return Err(format!("<{}> has no {:?} attribute", name, &attr[1]));
+            }
+            if name == "say-as" && &attr[1] == "interpret-as" && !INTERPRET_AS.contains(&&attr[2]) {
+                return Err(format!("<say-as> can't interpret as {:?}", &attr[2]));
+            }
+        }
+        if name == "say-as" && !attrs.contains("interpret-as") {
+            return Err("<say-as> needs an interpret-as".to_owned());
+        }
+        if name == "break" && !self_closing {
+            return Err("<break> has to be written as <break/>".to_owned());
+        }
+        if !self_closing {
+            open.push(name);
+        }
+    }
+    check_plain(&text[last_end..])?;
+
+    match open.pop() {
+        Some(name) => Err(format!("<{}> is never closed", name)),
+        None => Ok(()),
+    }
+}
+
+fn check_plain(text: &str) -> Result<(), String> {
+    match text.find('<') {
+        Some(i) => Err(format!("{:?} isn't an SSML tag", &text[i..].split_whitespace().next().unwrap_or("<"))),
+        None => Ok(()),
+    }
+}
+
+/// Text as the contents of a <Say>. The tags are kept and everything else is escaped, if the
+/// tags don't check out they're dropped instead so the TwiML is still valid. Text that's already
+/// been through `escape` (template values) isn't escaped twice
+pub fn to_xml(text: &str) -> String {
+    let keep_tags = match check(text) {
+        Ok(()) => true,
+        Err(e) => {
+            println!("Saying {:?} without its SSML: {}", text, e);
+            false
+        }
+    };
+
+    let mut out = String::new();
+    let mut last_end = 0;
+    TAG_RE.with(|tag_re| for tag in tag_re.find_iter(text) {
+        out += &escape_text(&text[last_end..tag.start()]);
+        if keep_tags {
+            out += tag.as_str();
+        }
+        last_end = tag.end();
+    });
+    out + &escape_text(&text[last_end..])
+}
+
+/// Makes a value safe to put in a prompt, so a template variable with "<break/>" in it is said
+/// as text rather than read as a tag
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Undoes escape, for showing what's said
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+/// Like escape, but the entities escape makes are left alone
+fn escape_text(text: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in text.char_indices() {
+        match c {
+            '&' if ["&amp;", "&lt;", "&gt;"].iter().any(|entity| text[i..].starts_with(entity)) => out.push('&'),
+            '&' => out += "&amp;",
+            '<' => out += "&lt;",
+            '>' => out += "&gt;",
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Twilio's basic voices (man, woman, alice) ignore SSML tags
+pub fn voice_supports_ssml(voice: &str) -> bool {
+    voice.starts_with("Polly.") || voice.starts_with("Google.")
+}
@@ -22,7 +22,8 @@ pub struct Twilio {
 pub enum TwilioResponseError {
     HttpRequestError(hyper::error::Error),
     HttpStatusError(hyper::Response),
-    CouldntParseJsonError(String, serde_json::error::Error)
+    CouldntParseJsonError(String, serde_json::error::Error),
+    InvalidUrl(String),
 }
 
 
@@ -53,6 +54,30 @@ impl Twilio {
     }
 
 
+    /// POSTs json to a url that isn't twilio's, e.g. a script's webhook, using the same client as
+    /// the twilio requests. Evaluates to the response's body, any status other than 2xx is an error
+    pub fn post_json(&self, url: &str, json: String) -> Box<Future<Item=String, Error=TwilioResponseError>> {
+        let uri = match url.parse() {
+            Ok(uri) => uri,
+            Err(_) => return Box::new(futures::future::err(TwilioResponseError::InvalidUrl(url.to_owned()))),
+        };
+        let mut req: hyper::Request<hyper::Body> = hyper::Request::new(hyper::Method::Post, uri);
+        req.headers_mut().set(ContentType::json());
+        req.set_body(json);
+
+        Box::new(self.hyper_client.request(req)
+            .map_err(|e| TwilioResponseError::HttpRequestError(e))
+            .and_then(|resp| {
+                if !resp.status().is_success() {
+                    return Err(TwilioResponseError::HttpStatusError(resp));
+                }
+                Ok(resp)
+            })
+            .and_then(|resp| resp.body().concat2().map_err(|e| TwilioResponseError::HttpRequestError(e)))
+            .map(|body_bytes| String::from_utf8_lossy(&body_bytes).into_owned()))
+    }
+
+
     /// Returns a future which represents a sent text message, on success it will evaluate to
     /// a serde_json::Value representing the json returned by the twilio api. It can fail for any
     /// of the following reasons: Sending the request failed, a network error, Twilio returned the
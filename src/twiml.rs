@@ -4,10 +4,18 @@ use std::convert::From;
 use hyper::mime;
 use hyper::header::{Basic, ContentType, ContentLength};
 
+use ssml;
+
 pub struct Twiml {
     data: String,
 }
 
+impl Twiml {
+    pub fn as_str(&self) -> &str {
+        &self.data
+    }
+}
+
 
 impl From<Twiml> for hyper::Response {
     fn from(owned_twiml:Twiml) -> hyper::Response {
@@ -24,20 +32,141 @@ impl From<String> for Twiml {
 }
 
 
-pub fn get_input(callback_url: &str, to_say: &str) -> Twiml {
+/// How twilio says text, the voice and language attributes of a <Say>
+#[derive(Debug, Clone, PartialEq)]
+pub struct Voice {
+    pub name: String,
+    /// A locale like "en-US"
+    pub language: String,
+}
+
+/// Part of what the caller hears, either spoken by twilio or an audio file it plays
+#[derive(Debug, PartialEq)]
+pub enum Segment {
+    Say(String, Voice),
+    /// The url of the audio file
+    Play(String),
+}
+
+/// <Say> and <Play> verbs for the segments, one per line after indent
+fn prompt_verbs(to_say: &[Segment], indent: &str) -> String {
+    to_say.iter()
+        .filter_map(|segment| match segment {
+            &Segment::Say(ref text, _) if text.trim().is_empty() => None,
+            &Segment::Say(ref text, ref voice) => Some(format!(r#"<Say voice="{}" language="{}">{}</Say>"#, voice.name, voice.language, ssml::to_xml(text.trim()))),
+            &Segment::Play(ref url) => Some(format!("<Play>{}</Play>", url.replace("&", "&amp;"))),
+        })
+        .map(|verb| format!("\n{}{}", indent, verb))
+        .collect()
+}
+
+/// Escapes text for an XML attribute or element, e.g. keywords like "AT&T"
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// What a <Gather> listens for, how many digits it waits for and which key ends the input early
+pub struct Gather {
+    pub input: &'static str,
+    pub num_digits: Option<u32>,
+    /// None turns finishOnKey off so # can be pressed as a menu key
+    pub finish_on_key: Option<char>,
+    /// Words we expect the caller to say, helps twilio's speech recognition
+    pub hints: Vec<String>,
+    /// The locale speech is recognized in, twilio's default is en-US
+    pub language: Option<String>,
+    /// Seconds to wait for the caller to start
+    pub timeout: u32,
+    /// Where twilio is sent when the caller doesn't say anything before the timeout, without it
+    /// twilio runs out of twiml and the call ends
+    pub no_input_url: Option<String>,
+}
+
+impl Gather {
+    /// A single key press, used for menus
+    pub fn single_key() -> Gather {
+        Gather { input: "dtmf", num_digits: Some(1), finish_on_key: None, hints: Vec::new(), language: None, timeout: 10, no_input_url: None }
+    }
+}
+
+
+pub fn get_input(callback_url: &str, to_say: &[Segment], gather: &Gather) -> Twiml {
+    let num_digits_attr = gather.num_digits.map_or(String::new(), |n| format!(r#" numDigits="{}""#, n));
+    let speech_attrs = if gather.input.contains("speech") {
+        let language_attr = gather.language.as_ref().map_or(String::new(), |l| format!(r#" language="{}""#, escape(l)));
+        format!(r#" speechTimeout="auto" hints="{}"{}"#, escape(&gather.hints.join(", ")), language_attr)
+    } else {
+        String::new()
+    };
+    // Twilio only moves on to the verb after the <Gather> if nothing was entered
+    let redirect = gather.no_input_url.as_ref().map_or(String::new(), |url| format!(r#"
+                    <Redirect method="POST">{}</Redirect>"#, url.replace("&", "&amp;")));
     Twiml::from(format!(r#"<?xml version="1.0" encoding="UTF-8"?>
                 <Response>
-                    <Gather input="dtmf" timeout="10" numDigits="1" action="{}">
-                        <Say voice="woman">{}</Say>
-                    </Gather>
+                    <Gather input="{}" timeout="{}"{}{} finishOnKey="{}" action="{}">{}
+                    </Gather>{}
+                </Response>
+                "#, gather.input, gather.timeout, num_digits_attr, speech_attrs, gather.finish_on_key.map_or(String::new(), |c| c.to_string()), callback_url.replace("&", "&amp;"),
+                prompt_verbs(to_say, "                        "), redirect))
+}
+
+/// Who a <Dial> calls and how, the caller hears to_say first
+pub struct Dial {
+    /// A phone number or a sip: URI
+    pub to: String,
+    pub caller_id: Option<String>,
+    pub timeout: u32,
+}
+
+/// Twilio posts DialCallStatus to callback_url once the dialed call is over or failed. to and
+/// caller_id usually come from the context so they're escaped
+pub fn dial(callback_url: &str, to_say: &[Segment], dial: &Dial) -> Twiml {
+    let caller_id_attr = dial.caller_id.as_ref().map_or(String::new(), |c| format!(r#" callerId="{}""#, escape(c)));
+    let noun = if dial.to.starts_with("sip:") { "Sip" } else { "Number" };
+    Twiml::from(format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+                <Response>{}
+                    <Dial timeout="{}"{} action="{}">
+                        <{}>{}</{}>
+                    </Dial>
                 </Response>
-                "#, callback_url.replace("&", "&amp;"), to_say))
+                "#, prompt_verbs(to_say, "                    "), dial.timeout, caller_id_attr, callback_url.replace("&", "&amp;"), noun, escape(&dial.to), noun))
+}
+
+/// How a <Record> behaves and where twilio tells us about the recording
+pub struct Record {
+    pub max_length: u32,
+    pub finish_on_key: char,
+    pub beep: bool,
+    /// Twilio posts the recording's url here once the file is ready
+    pub status_callback: String,
+    /// Twilio posts the transcription here when set
+    pub transcribe_callback: Option<String>,
 }
 
-pub fn say(to_say: &str) -> Twiml {
+/// Twilio posts RecordingUrl and RecordingDuration to callback_url once the caller is done
+pub fn record(callback_url: &str, to_say: &[Segment], record: &Record) -> Twiml {
+    let transcribe_attrs = record.transcribe_callback.as_ref().map_or(String::new(), |url| {
+        format!(r#" transcribe="true" transcribeCallback="{}""#, url.replace("&", "&amp;"))
+    });
     Twiml::from(format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+                <Response>{}
+                    <Record maxLength="{}" finishOnKey="{}" playBeep="{}" action="{}" recordingStatusCallback="{}"{}/>
+                </Response>
+                "#, prompt_verbs(to_say, "                    "), record.max_length, record.finish_on_key, record.beep, callback_url.replace("&", "&amp;"),
+                record.status_callback.replace("&", "&amp;"), transcribe_attrs))
+}
+
+pub fn hangup() -> Twiml {
+    Twiml::from(String::from(r#"<?xml version="1.0" encoding="UTF-8"?>
                 <Response>
-                    <Say voice="woman">{}</Say>
+                    <Hangup/>
+                </Response>
+                "#))
+}
+
+pub fn say(to_say: &[Segment]) -> Twiml {
+    Twiml::from(format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+                <Response>{}
                 </Response>
-                "#, to_say))
+                "#, prompt_verbs(to_say, "                    ")))
 }
\ No newline at end of file
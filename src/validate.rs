@@ -0,0 +1,468 @@
+extern crate regex;
+
+use std::collections::HashMap;
+use std::fmt;
+
+use hours;
+use prompt;
+use ssml;
+use script::{ScriptBase, Action, Edge, Input, NUM_KEYS, SUBFLOW_STEP, key_char, key_index};
+
+
+#[derive(Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    pub severity: Severity,
+    /// Where in the tree the problem was found, e.g. `path "15"`
+    pub location: String,
+    pub msg: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} at {}: {}", self.severity, self.location, self.msg)
+    }
+}
+
+
+/// Walks the whole tree looking for mistakes that would otherwise only show up on a live call:
+/// GoToActions that loop forever or lead nowhere, nodes callers can't reach, empty prompts and
+/// template or condition variables the Context can't fill in. known_vars are the variables the Context
+/// knows about, variables captured from the caller by the script are known too.
+pub fn validate(sb: &ScriptBase, ctx_vars: &[&str]) -> Vec<Problem> {
+    let mut known_vars = ctx_vars.iter().map(|v| v.to_string()).collect::<Vec<String>>();
+    let mut problems = Vec::new();
+    find_captured_vars(&sb.root, "", &mut |path, var| {
+        if ctx_vars.contains(&var) {
+            problems.push(Problem {
+                severity: Severity::Warning,
+                location: format!("path {:?}", path),
+                msg: format!("input is saved as {:?} but the Context already has that variable, templates will use the Context's", var),
+            });
+        }
+        known_vars.push(var.to_owned());
+    });
+
+    if let Some(ref var) = sb.languages.context_var {
+        if !known_vars.contains(var) {
+            problems.push(Problem {
+                severity: Severity::Error,
+                location: "languages".to_owned(),
+                msg: format!("the call's language comes from unknown variable {:?}", var),
+            });
+        }
+    }
+
+    let mut translated_to = Vec::new();
+    find_translations(&sb.root, &mut translated_to);
+
+    let mut validator = Validator {
+        sb,
+        known_vars,
+        translated_to,
+        var_re: regex::Regex::new(r"\{([^{}]*)\}").unwrap(),
+        labels_seen: Vec::new(),
+        splits_seen: Vec::new(),
+        err_depth: 0,
+        flow_depth: 0,
+        problems,
+    };
+    validator.check_action(&sb.root, "", String::from(r#"path """#), false);
+    validator.problems
+}
+
+
+struct Validator<'a> {
+    sb: &'a ScriptBase,
+    known_vars: Vec<String>,
+    /// Every locale at least one prompt has a translation for
+    translated_to: Vec<String>,
+    var_re: regex::Regex,
+    labels_seen: Vec<String>,
+    splits_seen: Vec<String>,
+    /// How many error actions deep the walk currently is, nodes below one have no path
+    err_depth: usize,
+    /// How many sub-flows deep the walk currently is, a flow is walked again everywhere it's run
+    flow_depth: usize,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Validator<'a> {
+    fn report(&mut self, severity: Severity, location: &str, msg: String) {
+        self.problems.push(Problem { severity, location: location.to_owned(), msg });
+    }
+
+    fn check_action(&mut self, act: &Action, path: &str, location: String, is_err_action: bool) {
+        self.check_label(&location, act);
+
+        match act {
+            &Action::ExecuteScript(ref script) => {
+                self.check_text(&location, &script.text);
+                if let Some(ref msg) = script.err_message {
+                    self.check_text(&location, msg);
+                }
+
+                let translated_keywords = script.keyword_translations().values().flat_map(|keywords| keywords.iter());
+                let mut keyword_keys = script.keywords().iter().chain(translated_keywords).map(|&(key, _)| key).collect::<Vec<usize>>();
+                keyword_keys.sort();
+                keyword_keys.dedup();
+                for key in keyword_keys {
+                    if act.children().iter().all(|&(edge, _)| edge != Edge::Step(key_char(key))) {
+                        self.report(Severity::Error, &location, format!("keywords are set for key {} but it has no action", key));
+                    }
+                }
+
+                if script.input != Input::Dtmf && !script.keywords().is_empty() {
+                    let default = self.sb.languages.default.clone();
+                    let untranslated = self.translated_to.iter()
+                        .filter(|locale| **locale != default && !script.keyword_translations().contains_key(*locale))
+                        .cloned().collect::<Vec<String>>();
+                    for locale in untranslated {
+                        self.report(Severity::Warning, &location, format!("speech from calls in {} is recognized as {} and matched against the {} keywords, add keyword_translations for {}", locale, default, default, locale));
+                    }
+                }
+            }
+            &Action::Collect(ref collect) => {
+                self.check_text(&location, &collect.text);
+                if let Some(ref msg) = collect.err_message {
+                    self.check_text(&location, msg);
+                }
+                if collect.save_as.is_empty() {
+                    self.report(Severity::Error, &location, "collect has nowhere to save its input".to_owned());
+                }
+                self.check_finish_key(&location, collect.finish_on_key);
+            }
+            &Action::Record(ref record) => {
+                self.check_text(&location, &record.text);
+                if record.name.trim().is_empty() {
+                    self.report(Severity::Error, &location, "record has no name to tell its recordings apart".to_owned());
+                }
+                self.check_finish_key(&location, record.finish_on_key);
+            }
+            &Action::HangupWithMessage(ref hangup) => self.check_text(&location, &hangup.text),
+            &Action::GoToAction(_) | &Action::GoToLabel(_) => self.follow_jumps(&location, act, path, &mut Vec::new()),
+            &Action::Branch(ref branch) => {
+                let var = branch.condition.var_name();
+                if !self.known_vars.iter().any(|known| known == var) {
+                    self.report(Severity::Error, &location, format!("condition uses unknown variable {:?}", var));
+                }
+            }
+            &Action::Hours(ref hours) => {
+                if hours.schedule.weekly.is_empty() {
+                    self.report(Severity::Warning, &location, "the schedule has no opening hours, it's always closed".to_owned());
+                }
+                match hours.schedule.holidays_file {
+                    Some(ref file) => {
+                        if let Err(e) = hours::load_holidays(file) {
+                            self.report(Severity::Error, &location, e);
+                        }
+                    }
+                    None if hours.holiday.is_some() => {
+                        self.report(Severity::Warning, &location, "there's a holiday action but no holidays file, it's never run".to_owned());
+                    }
+                    None => {}
+                }
+            }
+            &Action::Split(ref split) => {
+                if split.name.trim().is_empty() {
+                    self.report(Severity::Error, &location, "split has no name to record its variants under".to_owned());
+                }
+                else if self.splits_seen.contains(&split.name) {
+                    self.report(Severity::Error, &location, format!("split name {:?} is used more than once, their variants would be mixed up", split.name));
+                }
+                else {
+                    self.splits_seen.push(split.name.clone());
+                }
+
+                if split.variants.len() > NUM_KEYS {
+                    self.report(Severity::Error, &location, format!("split has {} variants, it can have at most {}", split.variants.len(), NUM_KEYS));
+                }
+                if split.variants.is_empty() {
+                    self.report(Severity::Error, &location, "split has no variants".to_owned());
+                }
+                else if split.variants.iter().all(|v| v.weight == 0) {
+                    self.report(Severity::Error, &location, "none of the split's variants has any weight".to_owned());
+                }
+                else {
+                    for variant in split.variants.iter().filter(|v| v.weight == 0) {
+                        self.report(Severity::Warning, &location, format!("variant {:?} has no weight, no call gets it", variant.name));
+                    }
+                }
+                for (i, variant) in split.variants.iter().enumerate() {
+                    if split.variants[..i].iter().any(|v| v.name == variant.name) {
+                        self.report(Severity::Error, &location, format!("variant name {:?} is used more than once", variant.name));
+                    }
+                }
+            }
+            &Action::Return => {
+                if self.sb.return_path(path).is_none() {
+                    self.report(Severity::Error, &location, "return isn't inside a sub-flow, there's nothing to return to".to_owned());
+                }
+            }
+            // The flow is checked as the SubFlow's child
+            &Action::SubFlow(_) => {}
+            &Action::Repeat => {
+                if !is_err_action {
+                    self.report(Severity::Error, &location, "Repeat can only be used as an error action".to_owned());
+                }
+            }
+            // Always lead to a prompt relative to where the caller is, so there's nothing to check
+            &Action::Back | &Action::MainMenu | &Action::RepeatPrompt => {}
+            &Action::SendText(ref text) => {
+                self.check_text(&location, &text.text);
+                if let Some(ref to) = text.to {
+                    self.check_vars(&location, to);
+                }
+            }
+            &Action::SetLanguage(ref set) => {
+                if set.language.trim().is_empty() {
+                    self.report(Severity::Error, &location, "no language to switch to".to_owned());
+                }
+                else if set.language != self.sb.languages.default && !self.translated_to.contains(&set.language) {
+                    self.report(Severity::Warning, &location, format!("no prompt is translated to {:?}, the caller will keep hearing {}", set.language, self.sb.languages.default));
+                }
+            }
+            &Action::Webhook(ref hook) => {
+                if hook.url.trim().is_empty() {
+                    self.report(Severity::Error, &location, "webhook has no url".to_owned());
+                }
+                self.check_vars(&location, &hook.url);
+            }
+            &Action::Transfer(ref transfer) => {
+                if transfer.to.trim().is_empty() {
+                    self.report(Severity::Error, &location, "transfer has no number to dial".to_owned());
+                }
+                self.check_vars(&location, &transfer.to);
+                if let Some(ref caller_id) = transfer.caller_id {
+                    self.check_vars(&location, caller_id);
+                }
+                if let Some(ref text) = transfer.text {
+                    self.check_text(&location, text);
+                }
+            }
+        }
+
+        if let Some(msg) = act.no_input().and_then(|n| n.message.as_ref()) {
+            self.check_text(&location, msg);
+        }
+        for translations in translations_of(act) {
+            for (locale, text) in translations.iter() {
+                self.check_text_in(&location, text, locale);
+            }
+        }
+
+        let speech_only = match act {
+            &Action::ExecuteScript(ref script) => script.input == Input::Speech,
+            _ => false,
+        };
+
+        for (edge, child) in act.children() {
+            match edge {
+                Edge::Step(c) => {
+                    let child_path = format!("{}{}", path, c);
+                    let child_location = format!("path {:?}", child_path);
+
+                    if speech_only && key_index(c).is_some() && !has_keywords_for(act, c) {
+                        self.report(Severity::Warning, &child_location, format!("unreachable, the script only listens for speech and has no keywords for key {}", c));
+                    }
+                    let enters_flow = c == SUBFLOW_STEP;
+                    if enters_flow {
+                        self.flow_depth += 1;
+                    }
+                    self.check_action(child, &child_path, child_location, false);
+                    if enters_flow {
+                        self.flow_depth -= 1;
+                    }
+                }
+                Edge::Error | Edge::RetriesExceeded | Edge::NoInput => {
+                    let child_location = match edge {
+                        Edge::Error => format!("error action of {}", location),
+                        Edge::NoInput => format!("no input action of {}", location),
+                        _ => format!("retry limit action of {}", location),
+                    };
+                    // Error actions don't have a path, so anything that takes input, is reported back to its
+                    // path or goes on through a step of its own would be followed from the prompt's path instead
+                    if child.has_own_steps() {
+                        self.report(Severity::Error, &child_location, "error actions can't take input or carry on through steps of their own, use a GoToAction or GoToLabel to one elsewhere in the script instead".to_owned());
+                    }
+                    self.err_depth += 1;
+                    self.check_action(child, path, child_location, true);
+                    self.err_depth -= 1;
+                }
+            }
+        }
+    }
+
+    fn check_finish_key(&mut self, location: &str, key: char) {
+        if key_index(key).is_none() {
+            self.report(Severity::Error, location, format!("finish_on_key {:?} isn't a key on the phone, expected 0-9, * or #", key));
+        }
+    }
+
+    fn check_text(&mut self, location: &str, text: &str) {
+        let default = self.sb.languages.default.clone();
+        self.check_text_in(location, text, &default);
+    }
+
+    /// Checks text that's said in locale
+    fn check_text_in(&mut self, location: &str, text: &str, locale: &str) {
+        if text.trim().is_empty() {
+            self.report(Severity::Error, location, "prompt text is empty".to_owned());
+        }
+        self.check_vars(location, text);
+
+        for part in prompt::parts(text) {
+            if let prompt::Part::Text(text) = part {
+                // Tags can't go around an audio file since the text on each side of it is said separately
+                if let Err(e) = ssml::check(text) {
+                    self.report(Severity::Error, location, format!("invalid SSML, {}", e));
+                }
+                else if ssml::has_tags(text) && !ssml::voice_supports_ssml(self.sb.languages.voice(locale)) {
+                    let voice = self.sb.languages.voice(locale).to_owned();
+                    self.report(Severity::Warning, location, format!("SSML tags are ignored by the {:?} voice, use a Polly or Google voice for {}", voice, locale));
+                }
+            }
+            if let prompt::Part::Audio { file, fallback } = part {
+                if prompt::is_local(file) && prompt::local_path(file).is_none() {
+                    self.report(Severity::Error, location, format!("audio file {:?} has to be inside the {} directory", file, prompt::AUDIO_DIR));
+                }
+                else if !prompt::audio_exists(file) {
+                    let instead = if fallback.is_some() { "its fallback text will be said instead" } else { "it has no fallback text so nothing will be said" };
+                    self.report(Severity::Warning, location, format!("audio file {:?} isn't in the {} directory, {}", file, prompt::AUDIO_DIR, instead));
+                }
+            }
+        }
+    }
+
+    fn check_vars(&mut self, location: &str, text: &str) {
+        let unknown = self.var_re.captures_iter(text)
+            .map(|cap| cap.get(1).unwrap().as_str().to_owned())
+            .filter(|var| !self.known_vars.contains(var))
+            .collect::<Vec<String>>();
+        for var in unknown {
+            self.report(Severity::Error, location, format!("unknown template variable {{{}}}", var));
+        }
+    }
+
+    /// Follows GoToActions, GoToLabels, Returns, Branches, Hours, Splits, SubFlows, SendTexts, Webhooks and SetLanguages the same way the server would without waiting
+    /// for the caller, stopping if it ever comes back to a path it has already jumped to
+    fn follow_jumps(&mut self, location: &str, act: &Action, path: &str, seen: &mut Vec<String>) {
+        let target = match act {
+            &Action::GoToAction(ref target) => target.clone(),
+            &Action::GoToLabel(ref label) => match self.sb.path_of_label(label) {
+                Some(target) => target.to_owned(),
+                None => {
+                    self.report(Severity::Error, location, format!("no node is labeled {:?}", label));
+                    return;
+                }
+            },
+            // Returning outside a sub-flow is reported by check_action
+            &Action::Return => match self.sb.return_path(path) {
+                Some(target) => target,
+                None => return,
+            },
+            // Texts, webhooks and language changes go on to their next action without waiting for the caller too
+            &Action::Branch(_) | &Action::Hours(_) | &Action::Split(_) | &Action::SubFlow(_) | &Action::SendText(_) | &Action::Webhook(_) | &Action::SetLanguage(_) => {
+                for (edge, child) in act.children() {
+                    if let Edge::Step(c) = edge {
+                        self.follow_jumps(location, child, &format!("{}{}", path, c), seen);
+                    }
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        if seen.contains(&target) {
+            let chain = seen.iter().chain(Some(&target)).map(|t| format!("{:?}", t)).collect::<Vec<String>>();
+            self.report(Severity::Error, location, format!("jumps loop forever: {}", chain.join(" -> ")));
+            return;
+        }
+
+        match self.sb.node_at(&target) {
+            Some(next) => {
+                seen.push(target.clone());
+                self.follow_jumps(location, next, &target, seen);
+                seen.pop();
+            }
+            None => self.report(Severity::Error, location, format!("GoToAction target {:?} doesn't exist", target)),
+        }
+    }
+
+    fn check_label(&mut self, location: &str, act: &Action) {
+        if let Some(label) = act.label() {
+            if self.err_depth > 0 {
+                self.report(Severity::Error, location, format!("label {:?} is inside an error action, it can't be jumped to", label));
+            }
+            else if self.flow_depth > 0 {
+                self.report(Severity::Error, location, format!("label {:?} is inside a sub-flow, which has a copy everywhere it's run so it can't be jumped to", label));
+            }
+            else if self.labels_seen.iter().any(|l| l == label) {
+                self.report(Severity::Error, location, format!("label {:?} is used more than once", label));
+            }
+            else {
+                self.labels_seen.push(label.to_owned());
+            }
+        }
+    }
+}
+
+
+/// Calls f with the path and name of everything in the tree that saves the caller's input, and of
+/// the variables webhooks say they answer with
+fn find_captured_vars<F>(act: &Action, path: &str, f: &mut F) where F: FnMut(&str, &str) {
+    if let Some(var) = act.save_as() {
+        f(path, var);
+    }
+    if let &Action::Webhook(ref hook) = act {
+        for var in hook.provides.iter() {
+            f(path, var);
+        }
+    }
+    for (edge, child) in act.children() {
+        match edge {
+            Edge::Step(c) => find_captured_vars(child, &format!("{}{}", path, c), f),
+            _ => find_captured_vars(child, path, f),
+        }
+    }
+}
+
+/// Everything act says in other languages, locale -> text
+fn translations_of(act: &Action) -> Vec<&HashMap<String, String>> {
+    let mut translations = match act {
+        &Action::ExecuteScript(ref script) => vec![&script.translations, &script.err_translations],
+        &Action::Collect(ref collect) => vec![&collect.translations, &collect.err_translations],
+        &Action::HangupWithMessage(ref hangup) => vec![&hangup.translations],
+        &Action::Transfer(ref transfer) => vec![&transfer.translations],
+        &Action::Record(ref record) => vec![&record.translations],
+        &Action::SendText(ref text) => vec![&text.translations],
+        _ => Vec::new(),
+    };
+    if let Some(no_input) = act.no_input() {
+        translations.push(&no_input.translations);
+    }
+    translations
+}
+
+fn find_translations(act: &Action, locales: &mut Vec<String>) {
+    for locale in translations_of(act).into_iter().flat_map(|t| t.keys()) {
+        if !locales.contains(locale) {
+            locales.push(locale.clone());
+        }
+    }
+    for (_, child) in act.children() {
+        find_translations(child, locales);
+    }
+}
+
+fn has_keywords_for(act: &Action, key: char) -> bool {
+    match act {
+        &Action::ExecuteScript(ref script) => script.keywords().iter().any(|&(k, _)| key_char(k) == key),
+        _ => false,
+    }
+}
@@ -0,0 +1,190 @@
+//! Webhook actions ask another system what a call should do next. The webhook is sent
+//!
+//! ```json
+//! {"id": 3, "path": "12", "vars": {"f_name": "Sam", "zip": "02139"}}
+//! ```
+//!
+//! with every variable the call has so far, and can answer with
+//!
+//! ```json
+//! {"vars": {"balance": "12.50"}, "goto_label": "overdue"}
+//! ```
+//!
+//! where both fields are optional. Numbers and booleans in vars are stored as their JSON text.
+
+extern crate futures;
+extern crate serde_json;
+extern crate tokio_core;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use self::futures::Future;
+
+use twil_api;
+
+
+/// What the webhook answered with
+#[derive(Debug, Deserialize)]
+pub struct Decision {
+    #[serde(default)]
+    pub vars: HashMap<String, serde_json::Value>,
+    pub goto_label: Option<String>,
+}
+
+impl Decision {
+    /// The vars as they're stored in the call's session
+    pub fn string_vars(&self) -> Vec<(String, String)> {
+        self.vars.iter().map(|(name, value)| {
+            let value = match value {
+                &serde_json::Value::String(ref s) => s.clone(),
+                other => other.to_string(),
+            };
+            (name.clone(), value)
+        }).collect()
+    }
+}
+
+
+#[derive(Serialize)]
+struct Request<'a> {
+    id: i32,
+    path: &'a str,
+    vars: HashMap<&'a str, &'a str>,
+}
+
+pub fn request_body(id: i32, path: &str, vars: &[(&str, &str)]) -> String {
+    let request = Request { id, path, vars: vars.iter().cloned().collect() };
+    serde_json::to_string(&request).expect("Couldn't serialize webhook request")
+}
+
+pub fn parse_decision(body: &str) -> Result<Decision, String> {
+    serde_json::from_str(body).map_err(|e| format!("Invalid webhook response {:?}: {}", body, e))
+}
+
+/// POSTs body to url and evaluates to the response's body, or to why there isn't one if the
+/// request failed or took longer than timeout seconds
+pub fn post(twilio: &twil_api::Twilio, handle: &tokio_core::reactor::Handle, url: &str, body: String, timeout: u32)
+            -> Box<Future<Item=String, Error=String>> {
+    let request = twilio.post_json(url, body).map_err(|e| format!("{:?}", e));
+    let timer = match tokio_core::reactor::Timeout::new(Duration::from_secs(timeout as u64), handle) {
+        Ok(timer) => timer,
+        Err(e) => return Box::new(futures::future::err(format!("Couldn't start the timeout: {}", e))),
+    };
+    let timer = timer.then(move |_| Err(format!("No answer within {} seconds", timeout)));
+    Box::new(request.select(timer).map(|(body, _)| body).map_err(|(e, _)| e))
+}
+
+
+#[cfg(test)]
+mod tests {
+    extern crate hyper;
+
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use self::hyper::StatusCode;
+    use self::hyper::server::{Http, Request, Response, const_service, service_fn};
+    use super::futures::{Future, Stream};
+    use super::tokio_core::net::TcpListener;
+    use super::tokio_core::reactor::{Core, Timeout};
+
+    use call_flow::{self, Outcome};
+    use ctxmgr::ContextManager;
+    use hours::SystemClock;
+    use script::{Action, Hangup, Script, ScriptBase, Webhook};
+    use twil_api::Twilio;
+    use ExampleUserContext;
+
+    /// Starts a server on 127.0.0.1 that answers every request with status and body after delay
+    /// seconds, returns its url
+    fn serve(core: &Core, status: StatusCode, body: &'static str, delay: u64) -> String {
+        let handle = core.handle();
+        let timer_handle = handle.clone();
+        let service = const_service(service_fn(move |_: Request| {
+            Timeout::new(Duration::from_secs(delay), &timer_handle).unwrap()
+                .map(move |_| Response::<hyper::Body>::new().with_status(status).with_body(body))
+                .map_err(hyper::Error::from)
+        }));
+        let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = TcpListener::from_listener(listener, &addr, &handle).unwrap();
+        let server = Http::new().serve_incoming(listener.incoming().map(|(stream, _)| stream), service);
+        let url = format!("http://{}/hook", addr);
+        let conn_handle = handle.clone();
+        handle.spawn(server.for_each(move |conn| {
+            conn_handle.spawn(conn.map(|_| ()).map_err(|_| ()));
+            Ok(())
+        }).map_err(|_| ()));
+        url
+    }
+
+    /// Pressing 1 runs a webhook with a 1 second timeout, the "overdue" script is under 2
+    fn script(url: &str) -> ScriptBase {
+        let hook = Webhook::new(url, Action::HangupWithMessage(Hangup::with_text("Your balance is {balance}")),
+                                Action::HangupWithMessage(Hangup::with_text("Fallback"))).timeout(1);
+        ScriptBase::from_root(Script::with_text("Press 1")
+            .on(1, Action::Webhook(hook))
+            .on(2, Action::ExecuteScript(Script::with_text("Your account is overdue").labeled("overdue"))))
+    }
+
+    /// What the caller hears after pressing 1, once the webhook at url has answered
+    fn said_after_webhook(core: &mut Core, url: &str, ctx_mgr: &RefCell<ContextManager<ExampleUserContext>>) -> String {
+        let sb = script(url);
+        let id = ctx_mgr.borrow_mut().insert_context(ExampleUserContext { f_name: "will".to_owned(), l_name: "keat".to_owned() });
+        let mut body_params = HashMap::new();
+        body_params.insert("Digits".to_owned(), "1".to_owned());
+        let call = match call_flow::respond(&sb, ctx_mgr, id, "", false, &body_params, "http://localhost", "example", &SystemClock) {
+            Ok(Outcome::Webhook(call)) => call,
+            other => panic!("Expected the webhook to be called, got {:?}", other.map(|_| ())),
+        };
+        let twilio = Twilio::new(&core.handle());
+        let request = super::post(&twilio, &core.handle(), &call.url, call.body.clone(), call.timeout);
+        let answer = core.run(request);
+        match call_flow::webhook_done(&sb, ctx_mgr, id, call, answer, "http://localhost", "example", &SystemClock) {
+            Ok(Outcome::Reply(reply)) => reply.said,
+            other => panic!("Expected a reply, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn said_for(status: StatusCode, body: &'static str, delay: u64) -> String {
+        let mut core = Core::new().unwrap();
+        let url = serve(&core, status, body, delay);
+        said_after_webhook(&mut core, &url, &RefCell::new(ContextManager::new()))
+    }
+
+    #[test]
+    fn goto_label_jumps_to_the_label() {
+        assert_eq!(said_for(StatusCode::Ok, r#"{"goto_label": "overdue"}"#, 0), "Your account is overdue");
+    }
+
+    #[test]
+    fn vars_are_stored() {
+        let mut core = Core::new().unwrap();
+        let url = serve(&core, StatusCode::Ok, r#"{"vars": {"balance": 12.5}}"#, 0);
+        let ctx_mgr = RefCell::new(ContextManager::new());
+        assert_eq!(said_after_webhook(&mut core, &url, &ctx_mgr), "Your balance is 12.5");
+        assert_eq!(ctx_mgr.borrow().load_input(1, "balance"), Some("12.5"));
+    }
+
+    #[test]
+    fn non_2xx_goes_to_fallback() {
+        assert_eq!(said_for(StatusCode::InternalServerError, r#"{"goto_label": "overdue"}"#, 0), "Fallback");
+    }
+
+    #[test]
+    fn bad_json_goes_to_fallback() {
+        assert_eq!(said_for(StatusCode::Ok, "{\"vars\": ", 0), "Fallback");
+    }
+
+    #[test]
+    fn unknown_label_goes_to_fallback() {
+        assert_eq!(said_for(StatusCode::Ok, r#"{"goto_label": "nowhere"}"#, 0), "Fallback");
+    }
+
+    #[test]
+    fn timeout_goes_to_fallback() {
+        assert_eq!(said_for(StatusCode::Ok, "{}", 3), "Fallback");
+    }
+}